@@ -2,9 +2,6 @@ use crate::MyError;
 
 use super::ActionState;
 
-#[derive(Deserialize)]
-pub struct SingleCommandConfig {}
-
 pub struct Command {
     command: String,
     args: Vec<String>,
@@ -25,6 +22,8 @@ impl Command {
             return Ok(ActionState::Playing);
         }
 
+        // Fire and forget, the command is never waited for
+        #[allow(clippy::zombie_processes)]
         let _child = std::process::Command::new(self.command.clone())
             .args(self.args.clone())
             .spawn()
@@ -41,12 +40,12 @@ impl Command {
 
             if duration > std::time::Duration::from_secs(1) {
                 self.last_executed = None;
-                return Ok(ActionState::Stopped);
+                Ok(ActionState::Stopped)
             } else {
-                return Ok(ActionState::Playing);
+                Ok(ActionState::Playing)
             }
         } else {
-            return Ok(ActionState::None);
+            Ok(ActionState::None)
         }
     }
 
@@ -55,12 +54,12 @@ impl Command {
             let duration = std::time::Instant::now() - last_executed;
 
             if duration > std::time::Duration::from_secs(1) {
-                return ActionState::Stopped;
+                ActionState::Stopped
             } else {
-                return ActionState::Playing;
+                ActionState::Playing
             }
         } else {
-            return ActionState::None;
+            ActionState::None
         }
     }
 }
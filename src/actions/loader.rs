@@ -0,0 +1,128 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::MyError;
+
+use super::{
+    sound::{Sound, SoundSettings},
+    ActionSlot,
+};
+
+// Loading is mostly waiting for the disk, a few workers are enough to keep it busy
+const MAX_WORKERS: usize = 4;
+
+/// Everything needed to load a sound, prepared on the main thread.
+pub struct SoundLoad {
+    pub name: String,
+    pub files: Vec<PathBuf>,
+    pub settings: SoundSettings,
+    // The path as written in the board, for error messages
+    pub config_path: String,
+}
+
+impl SoundLoad {
+    fn load(self) -> Result<Sound, String> {
+        let config_path = self.config_path;
+
+        Sound::load(self.name, self.files, self.settings)
+            .map_err(|err| format!("Could not load \"{}\": {}", config_path, err))
+    }
+}
+
+/// A sound that finished loading, `ticket` is the one `SoundLoader::load` handed out for it.
+pub struct LoadedSound {
+    pub slot: ActionSlot,
+    pub ticket: u64,
+    pub sound: Result<Sound, String>,
+}
+
+struct LoadJob {
+    slot: ActionSlot,
+    ticket: u64,
+    load: SoundLoad,
+}
+
+/// Loads sounds on a pool of worker threads, so the board is usable while they load.
+pub struct SoundLoader {
+    jobs: Sender<LoadJob>,
+    finished: Receiver<LoadedSound>,
+    next_ticket: u64,
+}
+
+impl SoundLoader {
+    pub fn new() -> Result<SoundLoader, MyError> {
+        let (jobs, job_receiver) = channel::<LoadJob>();
+        let (finished_sender, finished) = channel();
+
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1)
+            .min(MAX_WORKERS);
+
+        for index in 0..workers {
+            let job_receiver = Arc::clone(&job_receiver);
+            let finished_sender = finished_sender.clone();
+
+            thread::Builder::new()
+                .name(format!("sound-loader-{}", index))
+                .spawn(move || loop {
+                    // Only held while waiting, so the other workers can load meanwhile
+                    let job = match job_receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => return,
+                    };
+
+                    // Ends the worker once the loader was dropped
+                    let Ok(job) = job else {
+                        return;
+                    };
+
+                    let loaded = LoadedSound {
+                        slot: job.slot,
+                        ticket: job.ticket,
+                        sound: job.load.load(),
+                    };
+
+                    if finished_sender.send(loaded).is_err() {
+                        return;
+                    }
+                })?;
+        }
+
+        Ok(SoundLoader {
+            jobs,
+            finished,
+            next_ticket: 0,
+        })
+    }
+
+    /// Queues a sound for `slot` and returns the ticket its result will carry.
+    ///
+    /// A slot that is loaded again before the first load finished gets a new ticket, which
+    /// tells the outdated result apart.
+    pub fn load(&mut self, slot: ActionSlot, load: SoundLoad) -> Result<u64, MyError> {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+
+        if self.jobs.send(LoadJob { slot, ticket, load }).is_err() {
+            return Err(MyError::SoundSystemError(
+                "The sound loader workers are gone.",
+            ));
+        }
+
+        Ok(ticket)
+    }
+
+    /// The next sound that finished loading, if any.
+    pub fn try_finished(&self) -> Option<LoadedSound> {
+        self.finished.try_recv().ok()
+    }
+}
@@ -1,35 +1,184 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
-use crate::{button_map::ButtonType, sound_system::SoundSystem, MyError};
+use crate::{
+    audio::{
+        bus::BusConfig,
+        ducking::DuckingConfig,
+        fade::{FadeCurve, DEFAULT_FADE_MS},
+    },
+    button_map::ButtonType,
+    sound_system::SoundSystem,
+    MyError,
+};
 
-use self::{command::Command, sound::Sound};
+use self::{
+    command::Command,
+    scene::{Scene, SceneSoundConfig},
+    sound::Sound,
+    variation::{Selection, SoundPaths},
+};
 
 pub mod command;
+pub mod loader;
+pub mod scene;
 pub mod sound;
+pub mod validation;
+pub mod variation;
+
+pub const DEFAULT_PAGE_NAME: &str = "Main";
+
+#[derive(Deserialize)]
+pub struct ActionConfigs {
+    // Actions outside of a page, they form a first page called "Main"
+    #[serde(default)]
+    pub actions: Vec<ActionConfig>,
+    #[serde(default)]
+    pub pages: Vec<PageConfig>,
+    // Sounds can be put on these to share a volume
+    #[serde(default)]
+    pub buses: Vec<BusConfig>,
+    // Buses turned down while others play
+    #[serde(default)]
+    pub ducking: Vec<DuckingConfig>,
+    // Playing pads light up with the level of their sound
+    #[serde(default)]
+    pub level_lights: bool,
+}
 
 #[derive(Deserialize)]
+pub struct PageConfig {
+    pub name: String,
+    pub actions: Vec<ActionConfig>,
+}
+
+/// A board as the sound mode uses it, all actions sorted into pages.
+pub struct Board {
+    pub pages: Vec<PageConfig>,
+    pub buses: Vec<BusConfig>,
+    pub ducking: Vec<DuckingConfig>,
+    pub level_lights: bool,
+}
+
+impl ActionConfigs {
+    /// Moves the actions outside of a page into their own first page.
+    pub fn into_board(mut self) -> Board {
+        if !self.actions.is_empty() {
+            self.pages.insert(
+                0,
+                PageConfig {
+                    name: DEFAULT_PAGE_NAME.to_string(),
+                    actions: self.actions,
+                },
+            );
+        }
+
+        Board {
+            pages: self.pages,
+            buses: self.buses,
+            ducking: self.ducking,
+            level_lights: self.level_lights,
+        }
+    }
+}
+
+/// Where an action sits, a button on one page of the board.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct ActionSlot {
+    pub page: String,
+    pub button: ButtonType,
+}
+
+// The variant names are what the board files are written in
+#[allow(clippy::enum_variant_names)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub enum ActionConfig {
     SoundConfig {
         button: ButtonType,
-        path: String,
+        // One file, a directory of variations or a list of variations
+        path: SoundPaths,
+        #[serde(default)]
+        selection: Selection,
         looping: bool,
         fade_in: bool,
         fade_out: bool,
+        #[serde(default = "default_fade_ms")]
+        fade_in_ms: u64,
+        #[serde(default = "default_fade_ms")]
+        fade_out_ms: u64,
+        #[serde(default)]
+        fade_curve: FadeCurve,
         gain: f32,
+        // Starting a sound fades out the other playing sounds of its group
+        #[serde(default)]
+        group: Option<String>,
+        // The bus whose volume the sound plays under, none plays straight to the master
+        #[serde(default)]
+        bus: Option<String>,
+        // Every press picks its speed (and so pitch) and gain factor from these ranges
+        #[serde(default)]
+        pitch_range: Option<(f32, f32)>,
+        #[serde(default)]
+        gain_range: Option<(f32, f32)>,
+        // Makes the random picks the same on every run
+        #[serde(default)]
+        seed: Option<u64>,
+        // Play from disk instead of memory, large files are streamed if not set
+        #[serde(default)]
+        streaming: Option<bool>,
+        // Decode to samples once at load so presses start without decoding
+        #[serde(default)]
+        predecode: bool,
     },
     CommandConfig {
         button: ButtonType,
         command: String,
         args: Vec<String>,
     },
+    SceneConfig {
+        button: ButtonType,
+        sounds: Vec<SceneSoundConfig>,
+    },
+}
+
+fn default_fade_ms() -> u64 {
+    DEFAULT_FADE_MS
+}
+
+impl ActionConfig {
+    pub fn button(&self) -> ButtonType {
+        match self {
+            ActionConfig::SoundConfig { button, .. } => *button,
+            ActionConfig::CommandConfig { button, .. } => *button,
+            ActionConfig::SceneConfig { button, .. } => *button,
+        }
+    }
+
+    /// Files the action plays and the directory they are read from, a change to them needs the
+    /// action to be reloaded.
+    pub fn asset_paths(&self) -> Vec<PathBuf> {
+        match self {
+            ActionConfig::SoundConfig { path, .. } => {
+                let mut paths = path.files().unwrap_or_default();
+                paths.extend(path.directory());
+
+                paths
+            }
+            ActionConfig::CommandConfig { .. } | ActionConfig::SceneConfig { .. } => vec![],
+        }
+    }
 }
 
 pub enum Action {
-    Sound(Sound),
+    // Boxed, a sound with its envelopes and variations is far bigger than the other actions
+    Sound(Box<Sound>),
     Command(Command),
+    Scene(Scene),
 }
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum ActionState {
     None,
     Stopped,
@@ -44,13 +193,16 @@ impl Action {
         match self {
             Action::Sound(sound) => {
                 if sound.looped {
-                    return 125_u8;
+                    125_u8
                 } else {
-                    return 56_u8;
+                    56_u8
                 }
             }
             Action::Command(_command) => {
-                return 72_u8;
+                72_u8
+            }
+            Action::Scene(_scene) => {
+                9_u8
             }
         }
     }
@@ -59,13 +211,16 @@ impl Action {
         match self {
             Action::Sound(sound) => {
                 if sound.looped {
-                    return 127u8;
+                    127u8
                 } else {
-                    return 126u8;
+                    126u8
                 }
             }
             Action::Command(_command) => {
-                return 123_u8;
+                123_u8
+            }
+            Action::Scene(_scene) => {
+                122_u8
             }
         }
     }
@@ -77,6 +232,7 @@ impl Action {
         match self {
             Action::Sound(sound) => sound.play(sound_system),
             Action::Command(command) => command.execute(),
+            Action::Scene(scene) => Ok(scene.execute()),
         }
     }
 
@@ -86,10 +242,13 @@ impl Action {
     ) -> Result<ActionState, MyError> {
         match self {
             Action::Sound(sound) => {
-                return sound.update(sound_system);
+                sound.update(sound_system)
             }
             Action::Command(cmd) => {
-                return cmd.update();
+                cmd.update()
+            }
+            Action::Scene(scene) => {
+                Ok(scene.is_running())
             }
         }
     }
@@ -98,6 +257,7 @@ impl Action {
         match self {
             Action::Sound(sound) => sound.is_running(),
             Action::Command(cmd) => cmd.is_running(),
+            Action::Scene(scene) => scene.is_running(),
         }
     }
 }
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use super::ActionState;
+
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct SceneSoundConfig {
+    // Name of the sound as shown on the display, the file name without extension
+    pub name: String,
+    pub gain: f32,
+}
+
+/// A set of sounds with their gains, crossfaded to at once.
+///
+/// The crossfade itself is done by the `SoundMode` as it touches every sound of the board, the
+/// scene only knows its targets and whether it was the last one triggered.
+pub struct Scene {
+    targets: HashMap<String, f32>,
+    active: bool,
+}
+
+impl Scene {
+    pub fn new(sounds: Vec<SceneSoundConfig>) -> Scene {
+        Scene {
+            targets: sounds
+                .into_iter()
+                .map(|sound| (sound.name, sound.gain))
+                .collect(),
+            active: false,
+        }
+    }
+
+    pub fn targets(&self) -> &HashMap<String, f32> {
+        &self.targets
+    }
+
+    pub fn execute(&mut self) -> ActionState {
+        self.active = true;
+
+        ActionState::Started
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    pub fn is_running(&self) -> ActionState {
+        if self.active {
+            ActionState::Playing
+        } else {
+            ActionState::None
+        }
+    }
+}
@@ -1,151 +1,341 @@
 use std::{
-    io::{self, Read},
-    path::PathBuf,
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek},
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use crate::lock_or_return_err;
 
-use rodio::Sink;
+use rodio::{Sink, Source};
+
+use crate::audio::{
+    bus::BusInput,
+    channel::Channel,
+    envelope::{EnvelopeControl, GainEnvelope},
+    fade::FadeCurve,
+    latency::{LatencyProbe, LatencyStats},
+    meter::LevelMeter,
+    pcm::{PcmData, PcmSource},
+};
 
 use crate::{sound_system::SoundSystem, MyError};
 
-use super::ActionState;
+use super::{
+    variation::{Randomizer, Selection, Trigger, VariationPicker},
+    ActionState,
+};
 
-#[derive(Deserialize)]
-pub struct SingleSoundConfig {}
+/// How a sound plays, everything of its config besides the files.
+pub struct SoundSettings {
+    pub looped: bool,
+    pub fade_in: bool,
+    pub fade_out: bool,
+    pub fade_in_duration: Duration,
+    pub fade_out_duration: Duration,
+    pub fade_curve: FadeCurve,
+    pub gain: f32,
+    pub group: Option<String>,
+    pub bus: Option<String>,
+    pub selection: Selection,
+    // Ranges the speed and gain of every press are picked from
+    pub pitch_range: Option<(f32, f32)>,
+    pub gain_range: Option<(f32, f32)>,
+    pub seed: Option<u64>,
+    // Streams files from disk instead of loading them, decided by file size if not set
+    pub streaming: Option<bool>,
+    // Decodes the files once at load instead of on every play
+    pub predecode: bool,
+}
+
+// Files larger than this are streamed from disk unless the config says otherwise
+const STREAMING_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Whether a file of `size` bytes is streamed when the config doesn't say.
+fn is_streamed(size: u64) -> bool {
+    size > STREAMING_THRESHOLD
+}
+
+/// One file of a sound, most sounds only have one.
+struct Variation {
+    name: String,
+    data: VariationData,
+}
+
+enum VariationData {
+    // The whole file, decoded from memory on every play
+    Memory(Arc<Vec<u8>>),
+    // Decoded straight from the file on every play, for long ambience tracks
+    Stream(PathBuf),
+    // Decoded once at load, plays without any decoding delay
+    Decoded(Arc<PcmData>),
+}
+
+/// What the sound system adds to a playback, taken when it starts.
+struct PlaybackRoute {
+    // When the playback was asked for, the trigger latency is counted from here
+    pressed: Instant,
+    latency: Arc<LatencyStats>,
+    channel: Channel,
+    // Volume and ducking of the bus the sound plays on
+    bus: BusInput,
+}
+
+/// Shared audio data handed to the decoder.
+struct SoundData(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for SoundData {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Checks that the file at `path` can be decoded, done at load so a broken file shows up before
+/// its pad is pressed.
+fn probe_decoder<R>(path: &Path, reader: R) -> io::Result<()>
+where
+    R: Read + Seek + Send + Sync + 'static,
+{
+    match rodio::Decoder::new(reader) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("\"{}\" can not be decoded: {}", path.display(), err),
+        )),
+    }
+}
 
 pub struct Sound {
     // Runtime Data
     state: ActionState,
-    volume: f32,
+    // Trim and level the mixer mode shows while the sound plays
+    channel: Channel,
+    // Gain of the current playback, ramped in the audio stream
+    envelope: Option<EnvelopeControl>,
     sink: Option<Sink>,
-    sound_data: Arc<Vec<u8>>,
+    variations: Vec<Variation>,
+    // What was rolled for the last press
+    trigger: Trigger,
+    randomizer: Randomizer,
 
     // Settings
     name: String,
     gain: f32,
     fade_out: bool,
     fade_in: bool,
+    fade_in_duration: Duration,
+    fade_out_duration: Duration,
+    fade_curve: FadeCurve,
     pub looped: bool,
-}
-
-impl AsRef<[u8]> for Sound {
-    fn as_ref(&self) -> &[u8] {
-        &self.sound_data
-    }
+    pub group: Option<String>,
+    bus: Option<String>,
 }
 
 impl Sound {
-    pub fn load(
-        filename: String,
-        looped: bool,
-        fade_in: bool,
-        fade_out: bool,
-        gain: f32,
-    ) -> io::Result<Sound> {
-        let path = PathBuf::from(filename);
+    /// Loads all `files` of a sound named `name`, every one of them is a variation.
+    pub fn load(name: String, files: Vec<PathBuf>, settings: SoundSettings) -> io::Result<Sound> {
+        let mut variations = vec![];
+
+        for path in files {
+            let streaming = match settings.streaming {
+                Some(streaming) => streaming,
+                None => is_streamed(fs::metadata(&path)?.len()),
+            };
+
+            let data = if settings.predecode {
+                let file = BufReader::new(File::open(&path)?);
+
+                match PcmData::decode(file) {
+                    Ok(pcm) => VariationData::Decoded(Arc::new(pcm)),
+                    Err(err) => {
+                        return Err(io::Error::new(
+                            err.kind(),
+                            format!("\"{}\": {}", path.display(), err),
+                        ))
+                    }
+                }
+            } else if streaming {
+                // Fail now rather than on the first press
+                probe_decoder(&path, BufReader::new(File::open(&path)?))?;
 
-        let name = if let Some(stem) = path.file_stem() {
-            if let Some(file_name) = stem.to_str() {
-                String::from(file_name)
+                VariationData::Stream(path.clone())
             } else {
-                String::from("Unknown")
-            }
-        } else {
-            String::from("Unknown")
-        };
+                let mut buf = Vec::new();
+                let mut file = File::open(&path)?;
+                file.read_to_end(&mut buf)?;
+
+                let data = Arc::new(buf);
+                probe_decoder(&path, io::Cursor::new(SoundData(data.clone())))?;
 
-        use std::fs::File;
-        let mut buf = Vec::new();
-        let mut file = File::open(path)?;
-        file.read_to_end(&mut buf)?;
+                VariationData::Memory(data)
+            };
+
+            variations.push(Variation {
+                name: sound_name(&path),
+                data,
+            });
+        }
+
+        if variations.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no audio files for the sound",
+            ));
+        }
 
         Ok(Sound {
+            channel: Channel::new(name.clone()),
             name,
-            sound_data: Arc::new(buf),
+            randomizer: Randomizer::new(
+                VariationPicker::new(settings.selection, variations.len()),
+                settings.pitch_range,
+                settings.gain_range,
+                settings.seed,
+            ),
+            variations,
+            trigger: Trigger::default(),
             sink: None,
-            looped,
-            fade_in,
-            fade_out,
+            looped: settings.looped,
+            group: settings.group,
+            bus: settings.bus,
+            fade_in: settings.fade_in,
+            fade_out: settings.fade_out,
+            fade_in_duration: settings.fade_in_duration,
+            fade_out_duration: settings.fade_out_duration,
+            fade_curve: settings.fade_curve,
             state: ActionState::None,
-            gain,
-            volume: 0.0f32,
+            gain: settings.gain,
+            envelope: None,
         })
     }
 
-    pub fn cursor(self: &Self) -> io::Cursor<Sound> {
-        io::Cursor::new(Sound {
-            name: self.name.clone(),
-            sound_data: self.sound_data.clone(),
-            sink: None,
-            looped: self.looped,
-            fade_in: self.fade_in,
-            fade_out: self.fade_out,
-            state: self.state,
-            gain: self.gain,
-            volume: self.volume,
-        })
-    }
+    /// Where a new playback of the sound goes through on its way to the master.
+    fn route(&self, sound_system: &mut SoundSystem, pressed: Instant) -> PlaybackRoute {
+        sound_system.register_channel(&self.channel);
 
-    fn decoder(self: &Self) -> Result<rodio::Decoder<io::Cursor<Sound>>, MyError> {
-        match rodio::Decoder::new(self.cursor()) {
-            Ok(val) => Ok(val),
-            Err(_) => Err(MyError::SoundSystemError("Could not create sound decoder.")),
+        PlaybackRoute {
+            pressed,
+            latency: sound_system.latency_stats(),
+            channel: self.channel.clone(),
+            bus: sound_system.bus_input(self.bus.as_deref()),
         }
     }
 
-    fn looped_decoder(
-        self: &Self,
-    ) -> Result<rodio::decoder::LoopedDecoder<io::Cursor<Sound>>, MyError> {
-        match rodio::Decoder::new_looped(self.cursor()) {
-            Ok(val) => Ok(val),
-            Err(_) => Err(MyError::SoundSystemError(
-                "Could not create looped sound decoder.",
-            )),
+    /// Appends `source` to `sink` with the envelope and the rolled speed and gain.
+    fn append_playback<S>(
+        &self,
+        sink: &Sink,
+        envelope: &EnvelopeControl,
+        route: &PlaybackRoute,
+        source: S,
+    ) where
+        S: Source<Item = i16> + Send + 'static,
+    {
+        let source = source.speed(self.trigger.speed).amplify(self.trigger.gain);
+
+        let source = LatencyProbe::new(source, route.pressed, route.latency.clone());
+        let source = GainEnvelope::new(source, envelope.clone());
+
+        sink.append(route.bus.attach(route.channel.attach(source)));
+    }
+
+    fn append_decoded<R>(
+        &self,
+        sink: &Sink,
+        envelope: &EnvelopeControl,
+        route: &PlaybackRoute,
+        reader: R,
+    ) -> Result<(), MyError>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        if self.looped {
+            let source = match rodio::Decoder::new_looped(reader) {
+                Ok(val) => val,
+                Err(_) => {
+                    return Err(MyError::SoundSystemError(
+                        "Could not create looped sound decoder.",
+                    ))
+                }
+            };
+            self.append_playback(sink, envelope, route, source);
+        } else {
+            let source = match rodio::Decoder::new(reader) {
+                Ok(val) => val,
+                Err(_) => return Err(MyError::SoundSystemError("Could not create sound decoder.")),
+            };
+            self.append_playback(sink, envelope, route, source);
         }
+
+        Ok(())
     }
 
     fn create_sink_and_append(
         &mut self,
         sound_system: &Arc<Mutex<SoundSystem>>,
+        pressed: Instant,
     ) -> Result<ActionState, MyError> {
-        let sink = lock_or_return_err!(sound_system).get_sink()?;
+        let (sink, route) = {
+            let mut sound_system = lock_or_return_err!(sound_system);
+            (
+                sound_system.get_sink()?,
+                self.route(&mut sound_system, pressed),
+            )
+        };
 
-        let (new_state, volume) = self.append_to_sink(&sink, sound_system)?;
+        self.trigger = self.randomizer.roll();
+        let (new_state, envelope) = self.append_to_sink(&sink, &route)?;
         self.state = new_state;
-        self.volume = volume;
+        self.envelope = Some(envelope);
 
         self.sink = Some(sink);
 
         Ok(self.state)
     }
 
-    fn append_to_sink(
+    fn append_source(
         &self,
         sink: &Sink,
-        sound_system: &Arc<Mutex<SoundSystem>>,
-    ) -> Result<(ActionState, f32), MyError> {
-        if self.looped {
-            sink.append(self.looped_decoder()?);
-        } else {
-            sink.append(self.decoder()?);
+        envelope: &EnvelopeControl,
+        route: &PlaybackRoute,
+    ) -> Result<(), MyError> {
+        match &self.variations[self.trigger.variation].data {
+            VariationData::Memory(data) => self.append_decoded(
+                sink,
+                envelope,
+                route,
+                io::Cursor::new(SoundData(data.clone())),
+            ),
+            VariationData::Stream(path) => {
+                let file = File::open(path)?;
+                self.append_decoded(sink, envelope, route, BufReader::new(file))
+            }
+            VariationData::Decoded(pcm) => {
+                let source = PcmSource::new(pcm.clone(), self.looped);
+                self.append_playback(sink, envelope, route, source);
+                Ok(())
+            }
         }
+    }
 
+    /// Starts a playback on `sink`, fading in if the sound is set to.
+    fn append_to_sink(
+        &self,
+        sink: &Sink,
+        route: &PlaybackRoute,
+    ) -> Result<(ActionState, EnvelopeControl), MyError> {
         if self.fade_in {
-            sink.set_volume(0.0);
+            let envelope = EnvelopeControl::new(0.0);
+            envelope.ramp_to(self.gain, self.fade_in_duration, self.fade_curve);
+            self.append_source(sink, &envelope, route)?;
 
-            Ok((ActionState::FadingIn, 0.0))
+            Ok((ActionState::FadingIn, envelope))
         } else {
-            sink.set_volume(
-                sound_system
-                    .try_lock()
-                    .expect("Couldn't lock SoundSystem")
-                    .get_volume_factor()
-                    * self.gain,
-            );
+            let envelope = EnvelopeControl::new(self.gain);
+            self.append_source(sink, &envelope, route)?;
 
-            Ok((ActionState::Playing, self.gain))
+            Ok((ActionState::Playing, envelope))
         }
     }
 
@@ -153,8 +343,22 @@ impl Sound {
         self.name.clone()
     }
 
+    /// Name of the file played last, if the sound has more than one to pick from.
+    pub fn variation_name(&self) -> Option<String> {
+        if self.variations.len() > 1 {
+            Some(self.variations[self.trigger.variation].name.clone())
+        } else {
+            None
+        }
+    }
+
     pub fn is_playing(&self) -> bool {
-        return self.sink.is_some();
+        self.sink.is_some()
+    }
+
+    /// Level of the sound as it goes to its bus, after its gain and trim.
+    pub fn meter(&self) -> &LevelMeter {
+        self.channel.meter()
     }
 
     pub fn stop(&mut self) {
@@ -162,16 +366,83 @@ impl Sound {
             sink.stop();
         }
         self.sink = None;
+        self.envelope = None;
+    }
+
+    /// Fades from the current volume to `gain`, starting the sound first if it is not playing.
+    ///
+    /// Fading to silence fades the sound out and ends it, an idle sound is not started for it.
+    pub fn fade_to(
+        &mut self,
+        gain: f32,
+        sound_system: &Arc<Mutex<SoundSystem>>,
+    ) -> Result<ActionState, MyError> {
+        if gain <= 0.0 {
+            self.start_fade_out();
+            return Ok(self.state);
+        }
+
+        let pressed = Instant::now();
+        let sink_usable = self.sink.as_ref().is_some_and(|sink| !sink.empty());
+
+        let envelope = match (&self.envelope, sink_usable) {
+            (Some(envelope), true) => envelope.clone(),
+            _ => {
+                let (sink, route) = {
+                    let mut sound_system = lock_or_return_err!(sound_system);
+                    (
+                        sound_system.get_sink()?,
+                        self.route(&mut sound_system, pressed),
+                    )
+                };
+                let envelope = EnvelopeControl::new(0.0);
+
+                self.trigger = self.randomizer.roll();
+                self.append_source(&sink, &envelope, &route)?;
+
+                self.sink = Some(sink);
+                self.envelope = Some(envelope.clone());
+                envelope
+            }
+        };
+
+        let duration = if gain >= envelope.gain() {
+            self.fade_in_duration
+        } else {
+            self.fade_out_duration
+        };
+
+        envelope.ramp_to(gain, duration, self.fade_curve);
+        self.state = ActionState::FadingIn;
+
+        Ok(self.state)
+    }
+
+    /// Fades the sound out and stops it, regardless of its fade out setting.
+    pub fn start_fade_out(&mut self) {
+        if let Some(envelope) = &self.envelope {
+            envelope.ramp_to(0.0, self.fade_out_duration, self.fade_curve);
+            self.state = ActionState::FadingOut;
+        }
     }
 
     pub fn play(&mut self, sound_system: &Arc<Mutex<SoundSystem>>) -> Result<ActionState, MyError> {
+        // The latency starts here, waiting for the sound system and the decoder counts too
+        let pressed = Instant::now();
+
         if let Some(sink) = &self.sink {
             if sink.empty() {
-                let (new_state, volume) = self.append_to_sink(sink, sound_system)?;
+                let route = {
+                    let mut sound_system = lock_or_return_err!(sound_system);
+                    self.route(&mut sound_system, pressed)
+                };
+
+                self.trigger = self.randomizer.roll();
+                let (new_state, envelope) = self.append_to_sink(sink, &route)?;
                 self.state = new_state;
-                self.volume = volume;
+                self.envelope = Some(envelope);
 
-                return Ok(new_state);
+                Ok(new_state)
             } else {
                 let repress_mode = sound_system
                     .try_lock()
@@ -181,30 +452,29 @@ impl Sound {
                 match repress_mode {
                     crate::sound_system::RepressMode::End => {
                         if self.fade_out {
-                            self.state = ActionState::FadingOut;
-                            return Ok(self.state);
+                            self.start_fade_out();
+                            Ok(self.state)
                         } else {
-                            sink.stop();
-                            self.sink = None;
+                            self.stop();
                             self.state = ActionState::Stopped;
-                            return Ok(self.state);
+                            Ok(self.state)
                         }
                     }
                     crate::sound_system::RepressMode::Interrupt => {
-                        sink.stop();
-                        self.sink = None;
-                        return self.create_sink_and_append(sound_system);
+                        self.stop();
+                        self.create_sink_and_append(sound_system, pressed)
                     }
                 }
             }
         } else {
-            return self.create_sink_and_append(sound_system);
+            self.create_sink_and_append(sound_system, pressed)
         }
     }
 
+    /// Follows the fades done in the stream, the volume itself is not touched here.
     pub fn update(
         &mut self,
-        sound_system: &Arc<Mutex<SoundSystem>>,
+        _sound_system: &Arc<Mutex<SoundSystem>>,
     ) -> Result<ActionState, MyError> {
         if self.state == ActionState::Stopped {
             // We stopped last update, and now everything is over.
@@ -215,38 +485,19 @@ impl Sound {
         if let Some(sink) = &self.sink {
             if sink.empty() {
                 self.state = ActionState::Stopped;
-            } else {
-                const FRAMERATE: f32 = 60.0f32;
-                const INCREASE_PER_FRAME: f32 = 1.5f32;
-
-                match self.state {
-                    ActionState::FadingIn => {
-                        self.volume = f32::min(
-                            self.volume + (self.gain / FRAMERATE) * INCREASE_PER_FRAME,
-                            self.gain,
-                        );
-
-                        if self.volume >= self.gain {
-                            self.state = ActionState::Playing;
-                        }
-                    }
-                    ActionState::FadingOut => {
-                        self.volume = f32::max(
-                            self.volume - (self.gain / FRAMERATE) * INCREASE_PER_FRAME,
-                            0.0,
-                        );
-                        if self.volume <= 0.0 {
-                            self.state = ActionState::Stopped;
-                        }
-                    }
-                    _ => {
-                        // No need to act right now
-                    }
+            } else if self.state == ActionState::FadingIn || self.state == ActionState::FadingOut {
+                let settled = self
+                    .envelope
+                    .as_ref()
+                    .is_none_or(|envelope| envelope.is_settled());
+
+                if settled {
+                    self.state = if self.state == ActionState::FadingIn {
+                        ActionState::Playing
+                    } else {
+                        ActionState::Stopped
+                    };
                 }
-
-                sink.set_volume(
-                    lock_or_return_err!(sound_system).get_volume_factor() * self.volume,
-                );
             }
         } else {
             // We have no sink.
@@ -256,15 +507,112 @@ impl Sound {
         if self.state == ActionState::Stopped {
             // Nothing playing but still a sink
             // Take it out of option, stop and drop it.
-            if let Some(sink) = self.sink.take() {
-                sink.stop();
-            }
+            self.stop();
         }
 
-        return Ok(self.state);
+        Ok(self.state)
     }
 
     pub fn is_running(&self) -> ActionState {
         self.state
     }
+
+    /// The gain the current playback is at in the stream.
+    #[cfg(test)]
+    pub fn playback_gain(&self) -> Option<f32> {
+        self.envelope.as_ref().map(|envelope| envelope.gain())
+    }
+}
+
+/// Name of a sound as shown on the display and used by scenes, the file name without extension.
+pub fn sound_name(path: &Path) -> String {
+    if let Some(stem) = path.file_stem() {
+        if let Some(file_name) = stem.to_str() {
+            String::from(file_name)
+        } else {
+            String::from("Unknown")
+        }
+    } else {
+        String::from("Unknown")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::audio::output::OutputBackend;
+
+    fn settings(looped: bool, streaming: Option<bool>) -> SoundSettings {
+        SoundSettings {
+            looped,
+            fade_in: false,
+            fade_out: false,
+            fade_in_duration: Duration::ZERO,
+            fade_out_duration: Duration::ZERO,
+            fade_curve: FadeCurve::default(),
+            gain: 1.0,
+            group: None,
+            bus: None,
+            selection: Selection::default(),
+            pitch_range: None,
+            gain_range: None,
+            seed: None,
+            streaming,
+            predecode: false,
+        }
+    }
+
+    fn load_test_sound(settings: SoundSettings) -> Sound {
+        Sound::load(
+            "testsound".to_string(),
+            vec![PathBuf::from("assets/testsound.wav")],
+            settings,
+        )
+        .unwrap()
+    }
+
+    fn is_streaming(sound: &Sound) -> bool {
+        matches!(sound.variations[0].data, VariationData::Stream(_))
+    }
+
+    #[test]
+    fn files_above_16_mib_are_streamed() {
+        assert!(!is_streamed(16 * 1024 * 1024));
+        assert!(is_streamed(16 * 1024 * 1024 + 1));
+    }
+
+    #[test]
+    fn the_streaming_setting_overrides_the_file_size() {
+        assert!(!is_streaming(&load_test_sound(settings(false, None))));
+        assert!(is_streaming(&load_test_sound(settings(false, Some(true)))));
+        assert!(!is_streaming(&load_test_sound(settings(
+            false,
+            Some(false)
+        ))));
+    }
+
+    #[test]
+    fn streamed_sounds_loop() {
+        // Faster than real time, the two seconds of the test sound are soon over
+        let sound_system = Arc::new(Mutex::new(
+            SoundSystem::new(&OutputBackend::Null(20.0)).unwrap(),
+        ));
+        let mut looped = load_test_sound(settings(true, Some(true)));
+        let mut once = load_test_sound(settings(false, Some(true)));
+
+        looped.play(&sound_system).unwrap();
+        once.play(&sound_system).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while once.update(&sound_system).unwrap() == ActionState::Playing
+            && Instant::now() < deadline
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(once.is_running(), ActionState::Stopped);
+        assert_eq!(looped.update(&sound_system).unwrap(), ActionState::Playing);
+    }
 }
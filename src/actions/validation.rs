@@ -0,0 +1,491 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    fs::File,
+    io::BufReader,
+    path::Path,
+};
+
+use rodio::Source;
+
+use crate::{
+    audio::{bus::MAX_BUSES, pcm},
+    button_map::ButtonType,
+    DEFAULT_VOLUME, MAX_VOLUME,
+};
+
+use super::{sound::sound_name, variation::SoundPaths, ActionConfig, ActionConfigs, Board};
+
+const MAX_GAIN: f32 = MAX_VOLUME as f32 / DEFAULT_VOLUME as f32;
+
+// Two octaves down and up
+const MIN_PITCH: f32 = 0.25;
+const MAX_PITCH: f32 = 4.0;
+
+/// Reads and checks a board file and returns it with its actions sorted into pages.
+///
+/// Every problem found is returned as one line of text, so a broken board can be shown in
+/// full instead of stopping at the first mistake.
+pub fn load_board(path: &Path) -> Result<Board, Vec<String>> {
+    let config_string = match fs::read_to_string(path) {
+        Ok(value) => value,
+        Err(err) => return Err(vec![format!("{}: {}", path.display(), err)]),
+    };
+
+    let action_configs: ActionConfigs = match ron::de::from_str(&config_string) {
+        Ok(value) => value,
+        Err(err) => {
+            return Err(vec![format!(
+                "{}:{}:{}: {}",
+                path.display(),
+                err.position.line,
+                err.position.col,
+                err.code
+            )])
+        }
+    };
+
+    let board = action_configs.into_board();
+    let problems = validate(&board);
+
+    if problems.is_empty() {
+        Ok(board)
+    } else {
+        Err(problems)
+    }
+}
+
+pub fn validate(board: &Board) -> Vec<String> {
+    let mut problems = vec![];
+    let mut page_names = HashSet::new();
+    let mut bus_names = HashSet::new();
+
+    if board.buses.len() > MAX_BUSES {
+        problems.push(format!(
+            "{} buses are more than the {} encoders for them",
+            board.buses.len(),
+            MAX_BUSES
+        ));
+    }
+
+    for bus in &board.buses {
+        if !bus_names.insert(bus.name.as_str()) {
+            problems.push(format!("Bus \"{}\" exists more than once", bus.name));
+        }
+
+        if !(0.0..=MAX_GAIN).contains(&bus.volume) {
+            problems.push(format!(
+                "Bus \"{}\": volume {} is outside of 0.0 to {:.1}",
+                bus.name, bus.volume, MAX_GAIN
+            ));
+        }
+    }
+
+    for (index, rule) in board.ducking.iter().enumerate() {
+        let number = index + 1;
+
+        for bus in [&rule.trigger_bus, &rule.target_bus] {
+            if !bus_names.contains(bus.as_str()) {
+                problems.push(format!(
+                    "Ducking rule {}: bus \"{}\" is not in the buses of the board",
+                    number, bus
+                ));
+            }
+        }
+
+        if rule.trigger_bus == rule.target_bus {
+            problems.push(format!(
+                "Ducking rule {}: \"{}\" can't duck itself",
+                number, rule.trigger_bus
+            ));
+        }
+
+        if !(0.0..=1.0).contains(&rule.amount) {
+            problems.push(format!(
+                "Ducking rule {}: amount {} is outside of 0.0 to 1.0",
+                number, rule.amount
+            ));
+        }
+    }
+
+    // Scenes may name sounds of any page, by name with the group of the sound
+    let sound_groups: HashMap<String, Option<&str>> = board
+        .pages
+        .iter()
+        .flat_map(|page| &page.actions)
+        .filter_map(|action| match action {
+            ActionConfig::SoundConfig { path, group, .. } => {
+                Some((sound_name(Path::new(path.main_path())), group.as_deref()))
+            }
+            _ => None,
+        })
+        .collect();
+
+    for page in &board.pages {
+        if !page_names.insert(page.name.as_str()) {
+            problems.push(format!("Page \"{}\" exists more than once", page.name));
+        }
+
+        let mut first_use: HashMap<ButtonType, usize> = HashMap::new();
+
+        // Actions are numbered from 1 like in an editor
+        for (index, action) in page.actions.iter().enumerate() {
+            let number = index + 1;
+            let button = action.button();
+
+            if let Some(first) = first_use.get(&button) {
+                problems.push(format!(
+                    "Page \"{}\", action {}: {:?} is already used by action {}",
+                    page.name, number, button, first
+                ));
+            } else {
+                first_use.insert(button, number);
+            }
+
+            if let ActionConfig::SoundConfig {
+                path,
+                gain,
+                pitch_range,
+                gain_range,
+                streaming,
+                predecode,
+                bus,
+                ..
+            } = action
+            {
+                for problem in check_audio_files(path) {
+                    problems.push(format!(
+                        "Page \"{}\", action {}: {}",
+                        page.name, number, problem
+                    ));
+                }
+
+                if !(0.0..=MAX_GAIN).contains(gain) {
+                    problems.push(format!(
+                        "Page \"{}\", action {}: gain {} is outside of 0.0 to {:.1}",
+                        page.name, number, gain, MAX_GAIN
+                    ));
+                }
+
+                if let Some(problem) = check_range(pitch_range, MIN_PITCH, MAX_PITCH) {
+                    problems.push(format!(
+                        "Page \"{}\", action {}: pitch_range {}",
+                        page.name, number, problem
+                    ));
+                }
+
+                if let Some(problem) = check_range(gain_range, 0.0, MAX_GAIN) {
+                    problems.push(format!(
+                        "Page \"{}\", action {}: gain_range {}",
+                        page.name, number, problem
+                    ));
+                }
+
+                if *predecode {
+                    for problem in path
+                        .files()
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(|file| check_predecode_length(file))
+                    {
+                        problems.push(format!(
+                            "Page \"{}\", action {}: {}",
+                            page.name, number, problem
+                        ));
+                    }
+                }
+
+                if *predecode && *streaming == Some(true) {
+                    problems.push(format!(
+                        "Page \"{}\", action {}: predecode and streaming can't both be set",
+                        page.name, number
+                    ));
+                }
+
+                if let Some(bus) = bus
+                    && !bus_names.contains(bus.as_str())
+                {
+                    problems.push(format!(
+                        "Page \"{}\", action {}: bus \"{}\" is not in the buses of the board",
+                        page.name, number, bus
+                    ));
+                }
+            }
+
+            if let ActionConfig::SceneConfig { sounds, .. } = action {
+                // The sound of each group the scene starts, a group only ever plays one
+                let mut started_groups: HashMap<&str, &str> = HashMap::new();
+
+                for sound in sounds {
+                    if let Some(Some(group)) = sound_groups.get(&sound.name)
+                        && sound.gain > 0.0
+                        && let Some(other) = started_groups.insert(group, &sound.name)
+                    {
+                        problems.push(format!(
+                            "Page \"{}\", action {}: the scene starts \"{}\" and \"{}\" which are both in group \"{}\"",
+                            page.name, number, other, sound.name, group
+                        ));
+                    }
+
+                    if !sound_groups.contains_key(&sound.name) {
+                        problems.push(format!(
+                            "Page \"{}\", action {}: the scene names \"{}\" which is no sound of the board",
+                            page.name, number, sound.name
+                        ));
+                    }
+
+                    if !(0.0..=MAX_GAIN).contains(&sound.gain) {
+                        problems.push(format!(
+                            "Page \"{}\", action {}: gain {} of \"{}\" is outside of 0.0 to {:.1}",
+                            page.name, number, sound.gain, sound.name, MAX_GAIN
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+fn check_range(range: &Option<(f32, f32)>, lowest: f32, highest: f32) -> Option<String> {
+    let (min, max) = (*range)?;
+
+    if !min.is_finite() || !max.is_finite() {
+        Some(format!("({}, {}) has to be two numbers", min, max))
+    } else if min > max {
+        Some(format!("starts at {} which is above its end {}", min, max))
+    } else if min < lowest || max > highest {
+        Some(format!(
+            "({}, {}) is outside of {} to {}",
+            min, max, lowest, highest
+        ))
+    } else {
+        None
+    }
+}
+
+fn check_audio_files(paths: &SoundPaths) -> Vec<String> {
+    let files = match paths.files() {
+        Ok(value) => value,
+        Err(err) => return vec![format!("\"{}\": {}", paths.main_path(), err)],
+    };
+
+    if files.is_empty() {
+        return vec![format!("no audio files in \"{}\"", paths.main_path())];
+    }
+
+    files
+        .iter()
+        .filter_map(|file| check_audio_file(file))
+        .collect()
+}
+
+/// Only checks that the file can be opened, decoding it is left to the sound loader so a large
+/// board doesn't hold up the start.
+fn check_audio_file(path: &Path) -> Option<String> {
+    match File::open(path) {
+        Ok(_) => None,
+        Err(err) => Some(format!("audio file \"{}\": {}", path.display(), err)),
+    }
+}
+
+/// Reads the length from the header of the file, so a long file is reported before it is decoded.
+///
+/// Formats without the length in their header pass, the loader stops decoding them at the limit.
+fn check_predecode_length(path: &Path) -> Option<String> {
+    let file = BufReader::new(File::open(path).ok()?);
+    let duration = rodio::Decoder::new(file).ok()?.total_duration()?;
+
+    if duration > pcm::MAX_DURATION {
+        Some(format!(
+            "\"{}\" is {:.0} s long, predecode is for sounds up to {} s",
+            path.display(),
+            duration.as_secs_f32(),
+            pcm::MAX_DURATION.as_secs()
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::{scene_config, sound_config, TestDir};
+
+    /// Problems of a board with `actions`, its sounds are files in `dir`.
+    fn problems(dir: &TestDir, actions: &[String]) -> Vec<String> {
+        load_board(&dir.write_board(actions))
+            .err()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn scenes_can_not_start_two_sounds_of_a_group() {
+        let dir = TestDir::new("validation_scene_groups");
+        let group = ", group: Some(\"music\")";
+        let sounds = [
+            sound_config("Pad0x0", &dir.add_sound("a.wav"), true, group),
+            sound_config("Pad1x0", &dir.add_sound("b.wav"), true, group),
+        ];
+
+        let both = problems(
+            &dir,
+            &[
+                sounds[0].clone(),
+                sounds[1].clone(),
+                scene_config("Pad2x0", &[("a", 1.0), ("b", 0.5)]),
+            ],
+        );
+        assert_eq!(
+            both,
+            ["Page \"Main\", action 3: the scene starts \"a\" and \"b\" which are both in group \"music\""]
+        );
+
+        // Silencing one of them is fine
+        let one = problems(
+            &dir,
+            &[
+                sounds[0].clone(),
+                sounds[1].clone(),
+                scene_config("Pad2x0", &[("a", 1.0), ("b", 0.0)]),
+            ],
+        );
+        assert_eq!(one, Vec::<String>::new());
+    }
+
+    #[test]
+    fn buttons_can_only_be_used_once_per_page() {
+        let dir = TestDir::new("validation_buttons");
+        let sound = dir.add_sound("a.wav");
+        let twice = [
+            sound_config("Pad0x0", &sound, false, ""),
+            sound_config("Pad0x0", &sound, true, ""),
+        ];
+
+        assert_eq!(
+            problems(&dir, &twice),
+            ["Page \"Main\", action 2: Note(Pad0x0) is already used by action 1"]
+        );
+
+        // Other pages may use it again, that is what pages are for
+        let once = [sound_config("Pad0x0", &sound, false, "")];
+        let pages = load_board(&dir.write_pages(&[("Main", &once), ("Other", &once)]));
+        assert!(pages.is_ok(), "{:?}", pages.err());
+
+        let same_name = load_board(&dir.write_pages(&[("Main", &once), ("Main", &once)]));
+        assert_eq!(
+            same_name.err().unwrap_or_default(),
+            ["Page \"Main\" exists more than once"]
+        );
+    }
+
+    #[test]
+    fn gains_have_to_be_within_zero_and_four() {
+        let dir = TestDir::new("validation_gain");
+        let sound = sound_config("Pad0x0", &dir.add_sound("a.wav"), false, "");
+
+        for gain in ["-0.5", "4.5"] {
+            let action = sound.replace("gain: 1.0", &format!("gain: {}", gain));
+
+            assert_eq!(
+                problems(&dir, &[action]),
+                [format!(
+                    "Page \"Main\", action 1: gain {} is outside of 0.0 to 4.0",
+                    gain
+                )]
+            );
+        }
+
+        for gain in ["0.0", "4.0"] {
+            let action = sound.replace("gain: 1.0", &format!("gain: {}", gain));
+            assert_eq!(problems(&dir, &[action]), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn missing_files_are_reported() {
+        let dir = TestDir::new("validation_missing");
+        let missing = dir.join("missing.wav");
+
+        let problems = problems(&dir, &[sound_config("Pad0x0", &missing, false, "")]);
+
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].starts_with("Page \"Main\", action 1: ")
+                && problems[0].contains(missing.to_str().unwrap()),
+            "{}",
+            problems[0]
+        );
+    }
+
+    #[test]
+    fn only_short_sounds_can_be_predecoded() {
+        let dir = TestDir::new("validation_predecode");
+        // Just above the limit
+        let long = dir.add_silence("long.wav", 31);
+
+        let predecoded = |path: &Path| sound_config("Pad0x0", path, false, ", predecode: true");
+
+        assert_eq!(
+            problems(&dir, &[predecoded(&long)]),
+            [format!(
+                "Page \"Main\", action 1: \"{}\" is 31 s long, predecode is for sounds up to 30 s",
+                long.display()
+            )]
+        );
+        assert_eq!(
+            problems(&dir, &[predecoded(&dir.add_sound("short.wav"))]),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            problems(&dir, &[sound_config("Pad0x0", &long, false, "")]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn ranges_need_finite_bounds_in_order() {
+        let range = |range: (f32, f32)| check_range(&Some(range), MIN_PITCH, MAX_PITCH);
+
+        assert_eq!(range((0.5, 2.0)), None);
+        assert_eq!(range((1.0, 1.0)), None);
+        assert_eq!(
+            range((2.0, 0.5)),
+            Some("starts at 2 which is above its end 0.5".to_string())
+        );
+        assert_eq!(
+            range((f32::NAN, 2.0)),
+            Some("(NaN, 2) has to be two numbers".to_string())
+        );
+        assert_eq!(
+            range((0.5, f32::INFINITY)),
+            Some("(0.5, inf) has to be two numbers".to_string())
+        );
+        assert_eq!(
+            range((0.1, 2.0)),
+            Some("(0.1, 2) is outside of 0.25 to 4".to_string())
+        );
+    }
+
+    #[test]
+    fn syntax_errors_name_line_and_column() {
+        let dir = TestDir::new("validation_syntax");
+        let path = dir.join("board.ron");
+        std::fs::write(
+            &path,
+            "ActionConfigs(\n    actions: [\n        SoundConfig(button Note(Pad0x0)),\n",
+        )
+        .unwrap();
+
+        let problems = load_board(&path).err().unwrap_or_default();
+
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].starts_with(&format!("{}:3:28: ", path.display())),
+            "{}",
+            problems[0]
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+// Files picked up from a variation directory
+const AUDIO_EXTENSIONS: [&str; 4] = ["wav", "mp3", "ogg", "flac"];
+
+/// The `path` of a sound, one file, a directory of variations or a list of variations.
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum SoundPaths {
+    One(String),
+    Several(Vec<String>),
+}
+
+impl SoundPaths {
+    /// The audio files of the sound, a directory is expanded to its audio files in name order.
+    pub fn files(&self) -> io::Result<Vec<PathBuf>> {
+        match self {
+            SoundPaths::One(path) => {
+                let path = PathBuf::from(path);
+
+                if path.is_dir() {
+                    let mut files: Vec<PathBuf> = fs::read_dir(&path)?
+                        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                        .filter(|file| is_audio_file(file))
+                        .collect();
+                    files.sort();
+
+                    Ok(files)
+                } else {
+                    Ok(vec![path])
+                }
+            }
+            SoundPaths::Several(paths) => Ok(paths.iter().map(PathBuf::from).collect()),
+        }
+    }
+
+    /// The directory the variations are read from, if the sound has one.
+    pub fn directory(&self) -> Option<PathBuf> {
+        match self {
+            SoundPaths::One(path) if Path::new(path).is_dir() => Some(PathBuf::from(path)),
+            SoundPaths::One(_) | SoundPaths::Several(_) => None,
+        }
+    }
+
+    /// Path the sound is named after, the directory or the first file.
+    pub fn main_path(&self) -> &str {
+        match self {
+            SoundPaths::One(path) => path,
+            SoundPaths::Several(paths) => paths.first().map(String::as_str).unwrap_or_default(),
+        }
+    }
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+pub enum Selection {
+    #[default]
+    Random,
+    // Random, but never the same variation twice in a row
+    RandomNoRepeat,
+    RoundRobin,
+    // Every variation once in random order, then the next round
+    ShuffleBag,
+}
+
+/// What one press of a sound plays.
+#[derive(Clone, Copy)]
+pub struct Trigger {
+    pub variation: usize,
+    // Playback speed, which changes the pitch as well
+    pub speed: f32,
+    pub gain: f32,
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Trigger {
+            variation: 0,
+            speed: 1.0,
+            gain: 1.0,
+        }
+    }
+}
+
+/// Rolls the variation, pitch and gain for every press of a sound.
+///
+/// With a seed the rolls are the same on every run, e.g. to replay a recorded session.
+pub struct Randomizer {
+    picker: VariationPicker,
+    pitch_range: Option<(f32, f32)>,
+    gain_range: Option<(f32, f32)>,
+    rng: StdRng,
+}
+
+impl Randomizer {
+    pub fn new(
+        picker: VariationPicker,
+        pitch_range: Option<(f32, f32)>,
+        gain_range: Option<(f32, f32)>,
+        seed: Option<u64>,
+    ) -> Randomizer {
+        Randomizer {
+            picker,
+            pitch_range,
+            gain_range,
+            rng: match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_os_rng(),
+            },
+        }
+    }
+
+    pub fn roll(&mut self) -> Trigger {
+        let variation = self.picker.next(&mut self.rng);
+
+        let speed = match self.pitch_range {
+            Some((min, max)) => self.rng.random_range(min..=max),
+            None => 1.0,
+        };
+
+        let gain = match self.gain_range {
+            Some((min, max)) => self.rng.random_range(min..=max),
+            None => 1.0,
+        };
+
+        Trigger {
+            variation,
+            speed,
+            gain,
+        }
+    }
+}
+
+/// Decides which variation of a sound plays next.
+pub struct VariationPicker {
+    selection: Selection,
+    count: usize,
+    last: Option<usize>,
+    bag: Vec<usize>,
+}
+
+impl VariationPicker {
+    pub fn new(selection: Selection, count: usize) -> VariationPicker {
+        VariationPicker {
+            selection,
+            count,
+            last: None,
+            bag: vec![],
+        }
+    }
+
+    pub fn next(&mut self, rng: &mut StdRng) -> usize {
+        if self.count <= 1 {
+            return 0;
+        }
+
+        let index = match self.selection {
+            Selection::Random => rng.random_range(0..self.count),
+            Selection::RandomNoRepeat => {
+                // Pick from all but the last one by skipping over it
+                let pick = rng.random_range(0..self.count - 1);
+
+                match self.last {
+                    Some(last) if pick >= last => pick + 1,
+                    _ => pick,
+                }
+            }
+            Selection::RoundRobin => self.last.map_or(0, |last| (last + 1) % self.count),
+            Selection::ShuffleBag => {
+                if self.bag.is_empty() {
+                    self.bag = (0..self.count).collect();
+                    self.bag.shuffle(rng);
+
+                    // No repeat across two rounds either, the bag is taken from the back
+                    if self.bag.last() == self.last.as_ref() {
+                        let end = self.bag.len() - 1;
+                        self.bag.swap(0, end);
+                    }
+                }
+
+                self.bag.pop().unwrap_or_default()
+            }
+        };
+
+        self.last = Some(index);
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picks(selection: Selection, count: usize, seed: u64, number: usize) -> Vec<usize> {
+        let mut picker = VariationPicker::new(selection, count);
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        (0..number).map(|_| picker.next(&mut rng)).collect()
+    }
+
+    fn seeded_randomizer(seed: u64) -> Randomizer {
+        Randomizer::new(
+            VariationPicker::new(Selection::Random, 4),
+            Some((0.5, 2.0)),
+            Some((0.5, 1.0)),
+            Some(seed),
+        )
+    }
+
+    #[test]
+    fn same_seed_same_rolls() {
+        let mut first = seeded_randomizer(42);
+        let mut second = seeded_randomizer(42);
+
+        for _ in 0..100 {
+            let (first, second) = (first.roll(), second.roll());
+
+            assert_eq!(first.variation, second.variation);
+            assert_eq!(first.speed, second.speed);
+            assert_eq!(first.gain, second.gain);
+            assert!((0.5..=2.0).contains(&first.speed));
+            assert!((0.5..=1.0).contains(&first.gain));
+        }
+    }
+
+    #[test]
+    fn no_repeat_never_picks_the_previous_variation() {
+        for count in 2..6 {
+            let picks = picks(Selection::RandomNoRepeat, count, 1, 1000);
+
+            assert!(picks.iter().all(|pick| *pick < count));
+            assert!(
+                picks.windows(2).all(|pair| pair[0] != pair[1]),
+                "repeated a variation out of {}",
+                count
+            );
+        }
+    }
+
+    #[test]
+    fn shuffle_bag_plays_every_variation_once_per_round() {
+        for count in 2..6 {
+            let picks = picks(Selection::ShuffleBag, count, 3, count * 200);
+
+            // Across rounds as well
+            assert!(
+                picks.windows(2).all(|pair| pair[0] != pair[1]),
+                "repeated a variation out of {}",
+                count
+            );
+
+            for round in picks.chunks(count) {
+                let mut round = round.to_vec();
+                round.sort();
+
+                assert_eq!(round, (0..count).collect::<Vec<usize>>());
+            }
+        }
+    }
+
+    #[test]
+    fn round_robin_goes_through_in_order() {
+        assert_eq!(
+            picks(Selection::RoundRobin, 3, 0, 7),
+            vec![0, 1, 2, 0, 1, 2, 0]
+        );
+    }
+
+    #[test]
+    fn single_variation_is_always_picked() {
+        for selection in [
+            Selection::Random,
+            Selection::RandomNoRepeat,
+            Selection::RoundRobin,
+            Selection::ShuffleBag,
+        ] {
+            assert_eq!(picks(selection, 1, 0, 5), vec![0; 5]);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::{Sample, Source};
+
+use super::{
+    ducking::DuckingRule,
+    envelope::{EnvelopeControl, GainEnvelope, VOLUME_RAMP},
+    fade::FadeCurve,
+};
+
+// One encoder above the display per bus, the rightmost one is the master volume
+pub const MAX_BUSES: usize = 7;
+
+fn default_bus_volume() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct BusConfig {
+    pub name: String,
+    // Volume factor the bus starts with, like the gain of a sound
+    #[serde(default = "default_bus_volume")]
+    pub volume: f32,
+}
+
+/// A group of sounds sharing one volume, e.g. music, ambience or effects.
+///
+/// Every playback on the bus runs through its `BusInput`, so changing the volume reaches the
+/// sounds already playing.
+pub struct Bus {
+    name: String,
+    // In the steps of the master volume, `DEFAULT_VOLUME` is unity
+    volume: u32,
+    input: BusInput,
+
+    // Gain the ducking last ramped to and how long it takes to come back up from it
+    duck_gain: f32,
+    duck_release: Duration,
+}
+
+impl Bus {
+    pub fn new(config: &BusConfig) -> Bus {
+        let volume = (config.volume * crate::DEFAULT_VOLUME as f32).round() as u32;
+        let volume = volume.min(crate::MAX_VOLUME);
+
+        Bus {
+            name: config.name.clone(),
+            volume,
+            input: BusInput {
+                volume: EnvelopeControl::new(volume as f32 / crate::DEFAULT_VOLUME as f32),
+                duck: EnvelopeControl::new(1.0),
+                playing: Arc::new(AtomicUsize::new(0)),
+            },
+            duck_gain: 1.0,
+            duck_release: Duration::ZERO,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn input(&self) -> BusInput {
+        self.input.clone()
+    }
+
+    /// Whether any playback on the bus is still running.
+    pub fn is_playing(&self) -> bool {
+        self.input.playing.load(Ordering::Relaxed) > 0
+    }
+
+    /// Ducks the bus as `rule` says, or brings it back up if no rule applies anymore.
+    pub fn duck(&mut self, rule: Option<&DuckingRule>) {
+        let (gain, duration) = match rule {
+            Some(rule) => {
+                let duration = if rule.gain < self.duck_gain {
+                    rule.attack
+                } else {
+                    rule.release
+                };
+                self.duck_release = rule.release;
+
+                (rule.gain, duration)
+            }
+            None => (1.0, self.duck_release),
+        };
+
+        if gain != self.duck_gain {
+            self.duck_gain = gain;
+            self.input.duck.ramp_to(gain, duration, FadeCurve::Linear);
+        }
+    }
+
+    pub fn change_volume(&mut self, change: i16) {
+        if change.is_negative() {
+            self.volume = self.volume.saturating_sub(change.unsigned_abs() as u32);
+        } else {
+            self.volume = u32::min(crate::MAX_VOLUME, self.volume.saturating_add(change as u32));
+        }
+
+        self.input
+            .volume
+            .ramp_to(self.volume_factor(), VOLUME_RAMP, FadeCurve::Linear);
+    }
+
+    pub fn volume_factor(&self) -> f32 {
+        self.volume as f32 / crate::DEFAULT_VOLUME as f32
+    }
+}
+
+/// The volume and ducking a playback on a bus runs through.
+#[derive(Clone)]
+pub struct BusInput {
+    volume: EnvelopeControl,
+    duck: EnvelopeControl,
+    // Playbacks on the bus, a trigger bus of a ducking rule counts as playing while above 0
+    playing: Arc<AtomicUsize>,
+}
+
+impl BusInput {
+    /// For sounds without a bus, it leaves them as they are.
+    pub fn unity() -> BusInput {
+        BusInput {
+            volume: EnvelopeControl::new(1.0),
+            duck: EnvelopeControl::new(1.0),
+            playing: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Puts `source` on the bus, it counts as playing on it until it is dropped.
+    pub fn attach<S>(&self, source: S) -> BusSource<S>
+    where
+        S: Source,
+        S::Item: Sample,
+    {
+        self.playing.fetch_add(1, Ordering::Relaxed);
+
+        let source = GainEnvelope::new(source, self.volume.clone());
+
+        BusSource {
+            input: GainEnvelope::new(source, self.duck.clone()),
+            playing: self.playing.clone(),
+        }
+    }
+}
+
+/// A playback on a bus, see `BusInput::attach`.
+pub struct BusSource<S> {
+    input: GainEnvelope<GainEnvelope<S>>,
+    playing: Arc<AtomicUsize>,
+}
+
+impl<S> Drop for BusSource<S> {
+    fn drop(&mut self) {
+        self.playing.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<S> Iterator for BusSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.input.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S> Source for BusSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+    use crate::audio::ducking::DuckingConfig;
+
+    // One frame per millisecond, so ramps last as many samples as milliseconds
+    const SAMPLE_RATE: u32 = 1000;
+
+    fn bus() -> Bus {
+        Bus::new(&BusConfig {
+            name: "music".to_string(),
+            volume: 1.0,
+        })
+    }
+
+    /// Full scale for a second, what comes out of the bus is its gain.
+    fn ones(input: &BusInput) -> BusSource<SamplesBuffer<f32>> {
+        input.attach(SamplesBuffer::new(
+            1,
+            SAMPLE_RATE,
+            vec![1.0; SAMPLE_RATE as usize],
+        ))
+    }
+
+    /// Takes `ms` of samples and returns the last one.
+    fn gain_after<S: Iterator<Item = f32>>(source: &mut S, ms: usize) -> f32 {
+        source.take(ms).last().unwrap()
+    }
+
+    fn rule(amount: f32) -> DuckingRule {
+        DuckingRule::new(&DuckingConfig {
+            trigger_bus: "voice".to_string(),
+            target_bus: "music".to_string(),
+            amount,
+            attack_ms: 10,
+            release_ms: 20,
+        })
+    }
+
+    #[test]
+    fn ducks_over_the_attack_and_comes_back_over_the_release() {
+        let mut bus = bus();
+        let mut source = ones(&bus.input());
+
+        bus.duck(Some(&rule(0.75)));
+        assert!((gain_after(&mut source, 5) - 0.625).abs() < 0.05);
+        assert!((gain_after(&mut source, 5) - 0.25).abs() < 0.001);
+
+        bus.duck(None);
+        assert!((gain_after(&mut source, 10) - 0.625).abs() < 0.05);
+        assert!((gain_after(&mut source, 10) - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn counts_the_playbacks_on_it() {
+        let bus = bus();
+        assert!(!bus.is_playing());
+
+        let first = ones(&bus.input());
+        let second = ones(&bus.input());
+        drop(first);
+        assert!(bus.is_playing());
+
+        drop(second);
+        assert!(!bus.is_playing());
+    }
+}
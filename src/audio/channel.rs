@@ -0,0 +1,142 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::{Sample, Source};
+
+use super::{
+    envelope::{EnvelopeControl, GainEnvelope, VOLUME_RAMP},
+    fade::FadeCurve,
+    meter::{LevelMeter, MeterTap},
+};
+
+/// The live mix of one sound, shared by the sound and the sound system.
+///
+/// The mixer mode turns its trim while the sound plays, on top of the gain from the config.
+#[derive(Clone)]
+pub struct Channel {
+    name: String,
+    // In the steps of the master volume, `DEFAULT_VOLUME` is unity
+    trim: Arc<AtomicU32>,
+    control: EnvelopeControl,
+    meter: Arc<LevelMeter>,
+    // Playbacks of the sound still running
+    playing: Arc<AtomicUsize>,
+}
+
+impl Channel {
+    pub fn new(name: String) -> Channel {
+        Channel {
+            name,
+            trim: Arc::new(AtomicU32::new(crate::DEFAULT_VOLUME)),
+            control: EnvelopeControl::new(1.0),
+            meter: Arc::new(LevelMeter::default()),
+            playing: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether both are the same sound, clones share everything.
+    pub fn is_same(&self, other: &Channel) -> bool {
+        Arc::ptr_eq(&self.playing, &other.playing)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.load(Ordering::Relaxed) > 0
+    }
+
+    pub fn meter(&self) -> &LevelMeter {
+        &self.meter
+    }
+
+    pub fn change_trim(&self, change: i16) {
+        let trim = self.trim.load(Ordering::Relaxed);
+
+        let trim = if change.is_negative() {
+            trim.saturating_sub(change.unsigned_abs() as u32)
+        } else {
+            u32::min(crate::MAX_VOLUME, trim.saturating_add(change as u32))
+        };
+
+        self.trim.store(trim, Ordering::Relaxed);
+        self.control
+            .ramp_to(self.trim_factor(), VOLUME_RAMP, FadeCurve::Linear);
+    }
+
+    pub fn trim_factor(&self) -> f32 {
+        self.trim.load(Ordering::Relaxed) as f32 / crate::DEFAULT_VOLUME as f32
+    }
+
+    /// Runs `source` through the trim and meter, it counts as playing until it is dropped.
+    pub fn attach<S>(&self, source: S) -> ChannelSource<S>
+    where
+        S: Source,
+        S::Item: Sample,
+    {
+        self.playing.fetch_add(1, Ordering::Relaxed);
+
+        let source = GainEnvelope::new(source, self.control.clone());
+
+        ChannelSource {
+            input: MeterTap::new(source, self.meter.clone()),
+            playing: self.playing.clone(),
+        }
+    }
+}
+
+/// A playback of a sound, see `Channel::attach`.
+pub struct ChannelSource<S> {
+    input: MeterTap<GainEnvelope<S>>,
+    playing: Arc<AtomicUsize>,
+}
+
+impl<S> Drop for ChannelSource<S> {
+    fn drop(&mut self) {
+        self.playing.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<S> Iterator for ChannelSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.input.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S> Source for ChannelSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
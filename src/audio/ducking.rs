@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+fn default_attack_ms() -> u64 {
+    100
+}
+
+fn default_release_ms() -> u64 {
+    500
+}
+
+/// Turns `target_bus` down while anything plays on `trigger_bus`, e.g. music under narration.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct DuckingConfig {
+    pub trigger_bus: String,
+    pub target_bus: String,
+    // How much of the target's volume is taken away, 0.0 leaves it as is and 1.0 silences it
+    pub amount: f32,
+    #[serde(default = "default_attack_ms")]
+    pub attack_ms: u64,
+    #[serde(default = "default_release_ms")]
+    pub release_ms: u64,
+}
+
+/// A ducking config as the sound system applies it.
+pub struct DuckingRule {
+    pub trigger_bus: String,
+    pub target_bus: String,
+    // Gain of the target bus while ducked
+    pub gain: f32,
+    pub attack: Duration,
+    pub release: Duration,
+}
+
+impl DuckingRule {
+    pub fn new(config: &DuckingConfig) -> DuckingRule {
+        DuckingRule {
+            trigger_bus: config.trigger_bus.clone(),
+            target_bus: config.target_bus.clone(),
+            gain: 1.0 - config.amount.clamp(0.0, 1.0),
+            attack: Duration::from_millis(config.attack_ms),
+            release: Duration::from_millis(config.release_ms),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::{Sample, Source};
+
+use super::fade::{ramp_gain, FadeCurve};
+
+/// Volume changes from the encoders are smoothed over this long to keep them free of clicks.
+pub const VOLUME_RAMP: Duration = Duration::from_millis(30);
+
+/// Values shared between the controlling side and the audio thread.
+///
+/// Atomics instead of a mutex, the audio thread looks at them for every frame.
+struct EnvelopeShared {
+    // Written by the control
+    target: AtomicU32,
+    duration_us: AtomicU64,
+    curve: AtomicU8,
+    generation: AtomicU64,
+
+    // Written by the audio thread
+    gain: AtomicU32,
+    settled_generation: AtomicU64,
+}
+
+/// Controls the gain of a `GainEnvelope` from outside the audio thread.
+#[derive(Clone)]
+pub struct EnvelopeControl {
+    shared: Arc<EnvelopeShared>,
+}
+
+impl EnvelopeControl {
+    pub fn new(gain: f32) -> EnvelopeControl {
+        EnvelopeControl {
+            shared: Arc::new(EnvelopeShared {
+                target: AtomicU32::new(gain.to_bits()),
+                duration_us: AtomicU64::new(0),
+                curve: AtomicU8::new(FadeCurve::Linear as u8),
+                generation: AtomicU64::new(0),
+                gain: AtomicU32::new(gain.to_bits()),
+                settled_generation: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Ramps from wherever the gain is right now to `target` over `duration`.
+    pub fn ramp_to(&self, target: f32, duration: Duration, curve: FadeCurve) {
+        self.shared
+            .target
+            .store(target.to_bits(), Ordering::Relaxed);
+        self.shared
+            .duration_us
+            .store(duration.as_micros() as u64, Ordering::Relaxed);
+        self.shared.curve.store(curve as u8, Ordering::Relaxed);
+
+        // Publishes the values above to the audio thread
+        self.shared.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// The gain last applied in the stream.
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.shared.gain.load(Ordering::Relaxed))
+    }
+
+    /// Whether the stream has reached the target of the last ramp.
+    pub fn is_settled(&self) -> bool {
+        self.shared.settled_generation.load(Ordering::Acquire)
+            == self.shared.generation.load(Ordering::Acquire)
+    }
+}
+
+/// Applies a gain to every sample, ramping it sample by sample when asked to.
+///
+/// Ramps are counted in frames of the stream, so they are click free and end on time no matter
+/// how often the rest of the app gets to run.
+pub struct GainEnvelope<S> {
+    input: S,
+    control: EnvelopeControl,
+
+    generation: u64,
+    from: f32,
+    to: f32,
+    curve: FadeCurve,
+    position: u64,
+    length: u64,
+
+    gain: f32,
+    // Channel of the next sample, the gain only changes between frames
+    channel: u16,
+}
+
+impl<S> GainEnvelope<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    pub fn new(input: S, control: EnvelopeControl) -> GainEnvelope<S> {
+        let gain = control.gain();
+        let generation = control.shared.generation.load(Ordering::Acquire);
+
+        let mut envelope = GainEnvelope {
+            input,
+            control,
+            generation,
+            from: gain,
+            to: gain,
+            curve: FadeCurve::Linear,
+            position: 0,
+            length: 0,
+            gain,
+            channel: 0,
+        };
+
+        // A ramp requested before the source started playing still has to run
+        envelope.start_ramp(generation);
+
+        envelope
+    }
+
+    fn start_ramp(&mut self, generation: u64) {
+        let shared = &self.control.shared;
+
+        let duration = Duration::from_micros(shared.duration_us.load(Ordering::Relaxed));
+
+        self.generation = generation;
+        self.from = self.gain;
+        self.to = f32::from_bits(shared.target.load(Ordering::Relaxed));
+        self.curve = match shared.curve.load(Ordering::Relaxed) {
+            value if value == FadeCurve::EqualPower as u8 => FadeCurve::EqualPower,
+            value if value == FadeCurve::Logarithmic as u8 => FadeCurve::Logarithmic,
+            _ => FadeCurve::Linear,
+        };
+        self.position = 0;
+        self.length = (duration.as_secs_f64() * self.input.sample_rate() as f64) as u64;
+    }
+
+    /// Moves the envelope one frame ahead.
+    fn advance(&mut self) {
+        let shared = &self.control.shared;
+
+        let generation = shared.generation.load(Ordering::Acquire);
+        if generation != self.generation {
+            self.start_ramp(generation);
+        }
+
+        let shared = &self.control.shared;
+
+        if self.position < self.length {
+            self.position += 1;
+            self.gain = ramp_gain(
+                self.from,
+                self.to,
+                self.curve,
+                self.position as f32 / self.length as f32,
+            );
+        } else {
+            self.gain = self.to;
+        }
+
+        if self.position >= self.length {
+            shared
+                .settled_generation
+                .store(self.generation, Ordering::Release);
+        }
+
+        shared.gain.store(self.gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl<S> Iterator for GainEnvelope<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.advance();
+        }
+
+        let sample = self.input.next()?;
+        self.channel = (self.channel + 1) % self.input.channels().max(1);
+
+        Some(sample.amplify(self.gain))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S> Source for GainEnvelope<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    /// A second of full scale samples, one frame per millisecond.
+    fn constant(channels: u16) -> SamplesBuffer<f32> {
+        SamplesBuffer::new(
+            channels,
+            SAMPLE_RATE,
+            vec![1.0; SAMPLE_RATE as usize * channels as usize],
+        )
+    }
+
+    #[test]
+    fn ramp_reaches_its_target_after_the_fade_length() {
+        let control = EnvelopeControl::new(0.0);
+        control.ramp_to(1.0, Duration::from_millis(100), FadeCurve::Linear);
+        let mut envelope = GainEnvelope::new(constant(1), control.clone());
+
+        // 100 ms at 1000 Hz is 100 frames
+        for frame in 1..100 {
+            let sample = envelope.next().unwrap();
+            assert!(sample < 1.0, "full gain early at frame {}", frame);
+            assert!(!control.is_settled(), "settled early at frame {}", frame);
+        }
+
+        assert_eq!(envelope.next(), Some(1.0));
+        assert!(control.is_settled());
+        assert_eq!(control.gain(), 1.0);
+
+        assert!(envelope.take(100).all(|sample| sample == 1.0));
+    }
+
+    #[test]
+    fn ramp_starts_from_the_current_gain() {
+        let control = EnvelopeControl::new(1.0);
+        let mut envelope = GainEnvelope::new(constant(1), control.clone());
+
+        assert_eq!(envelope.next(), Some(1.0));
+        assert!(control.is_settled());
+
+        control.ramp_to(0.0, Duration::from_millis(50), FadeCurve::EqualPower);
+        assert!(!control.is_settled());
+
+        let faded: Vec<f32> = envelope.by_ref().take(50).collect();
+        assert!(faded.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(faded[49], 0.0);
+        assert!(control.is_settled());
+    }
+
+    #[test]
+    fn channels_of_a_frame_share_the_gain() {
+        let control = EnvelopeControl::new(0.0);
+        control.ramp_to(1.0, Duration::from_millis(100), FadeCurve::Logarithmic);
+        let envelope = GainEnvelope::new(constant(2), control);
+
+        let samples: Vec<f32> = envelope.take(200).collect();
+        assert!(samples.chunks(2).all(|frame| frame[0] == frame[1]));
+        assert_eq!(samples[199], 1.0);
+    }
+}
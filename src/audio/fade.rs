@@ -0,0 +1,119 @@
+use std::f32::consts::FRAC_PI_2;
+
+// The old frame based fades took about this long
+pub const DEFAULT_FADE_MS: u64 = 667;
+
+// Range a logarithmic fade covers, quieter than this counts as silence
+const LOGARITHMIC_RANGE_DB: f32 = 60.0;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+pub enum FadeCurve {
+    #[default]
+    Linear,
+    // Constant loudness when two sounds crossfade
+    EqualPower,
+    // Even steps in decibel, sounds even to the ear
+    Logarithmic,
+}
+
+impl FadeCurve {
+    /// Gain of a fade in at `progress` (0.0 to 1.0), a fade out runs it backwards.
+    pub fn shape(self, progress: f32) -> f32 {
+        let progress = progress.clamp(0.0, 1.0);
+
+        match self {
+            FadeCurve::Linear => progress,
+            FadeCurve::EqualPower => (progress * FRAC_PI_2).sin(),
+            FadeCurve::Logarithmic => {
+                if progress <= 0.0 {
+                    0.0
+                } else {
+                    10f32.powf((progress - 1.0) * LOGARITHMIC_RANGE_DB / 20.0)
+                }
+            }
+        }
+    }
+}
+
+/// Gain at `progress` (0.0 to 1.0) of a ramp from `from` to `to`.
+pub fn ramp_gain(from: f32, to: f32, curve: FadeCurve, progress: f32) -> f32 {
+    if to >= from {
+        from + (to - from) * curve.shape(progress)
+    } else {
+        // Fading down is a fade in played backwards
+        to + (from - to) * curve.shape(1.0 - progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [FadeCurve; 3] = [
+        FadeCurve::Linear,
+        FadeCurve::EqualPower,
+        FadeCurve::Logarithmic,
+    ];
+
+    const STEPS: usize = 1000;
+
+    #[test]
+    fn curves_start_silent_and_end_at_full_gain() {
+        for curve in CURVES {
+            assert_eq!(curve.shape(0.0), 0.0, "{:?}", curve);
+            assert!((curve.shape(1.0) - 1.0).abs() < 1e-6, "{:?}", curve);
+
+            // Progress outside the fade is held at its ends
+            assert_eq!(curve.shape(-0.5), curve.shape(0.0), "{:?}", curve);
+            assert_eq!(curve.shape(1.5), curve.shape(1.0), "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn curves_only_go_up() {
+        for curve in CURVES {
+            let gains: Vec<f32> = (0..=STEPS)
+                .map(|step| curve.shape(step as f32 / STEPS as f32))
+                .collect();
+
+            assert!(
+                gains.windows(2).all(|pair| pair[0] <= pair[1]),
+                "{:?} goes down",
+                curve
+            );
+        }
+    }
+
+    #[test]
+    fn ramps_run_from_start_to_target() {
+        for curve in CURVES {
+            for (from, to) in [(0.0, 1.0), (1.0, 0.0), (0.25, 0.75), (2.0, 0.5)] {
+                let gains: Vec<f32> = (0..=STEPS)
+                    .map(|step| ramp_gain(from, to, curve, step as f32 / STEPS as f32))
+                    .collect();
+
+                assert!(
+                    (gains[0] - from).abs() < 1e-6,
+                    "{:?} {} -> {}",
+                    curve,
+                    from,
+                    to
+                );
+                assert!(
+                    (gains[STEPS] - to).abs() < 1e-6,
+                    "{:?} {} -> {}",
+                    curve,
+                    from,
+                    to
+                );
+
+                let monotonic = if to >= from {
+                    gains.windows(2).all(|pair| pair[0] <= pair[1])
+                } else {
+                    gains.windows(2).all(|pair| pair[0] >= pair[1])
+                };
+                assert!(monotonic, "{:?} {} -> {} turns around", curve, from, to);
+            }
+        }
+    }
+}
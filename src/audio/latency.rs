@@ -0,0 +1,139 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rodio::Source;
+
+/// Time from pressing a pad until the audio thread pulls the first sample of the sound.
+///
+/// The output device adds its own buffer on top, which can't be measured from here.
+#[derive(Default)]
+pub struct LatencyStats {
+    count: AtomicU64,
+    total_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+impl LatencyStats {
+    pub fn record(&self, latency: Duration) {
+        let latency_us = latency.as_micros() as u64;
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(latency_us, Ordering::Relaxed);
+        self.max_us.fetch_max(latency_us, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> Option<String> {
+        let count = self.count.load(Ordering::Relaxed);
+
+        if count == 0 {
+            return None;
+        }
+
+        let average_ms = self.total_us.load(Ordering::Relaxed) as f64 / count as f64 / 1000.0;
+        let max_ms = self.max_us.load(Ordering::Relaxed) as f64 / 1000.0;
+
+        Some(format!(
+            "Trigger latency over {} presses: {:.1} ms average, {:.1} ms max",
+            count, average_ms, max_ms
+        ))
+    }
+}
+
+/// Passes a source through and records the latency once its first sample is pulled.
+pub struct LatencyProbe<S> {
+    input: S,
+    triggered: Instant,
+    // Taken once the latency is recorded
+    stats: Option<Arc<LatencyStats>>,
+}
+
+impl<S> LatencyProbe<S> {
+    pub fn new(input: S, triggered: Instant, stats: Arc<LatencyStats>) -> LatencyProbe<S> {
+        LatencyProbe {
+            input,
+            triggered,
+            stats: Some(stats),
+        }
+    }
+}
+
+impl<S> Iterator for LatencyProbe<S>
+where
+    S: Source,
+    S::Item: rodio::Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(stats) = self.stats.take() {
+            stats.record(self.triggered.elapsed());
+        }
+
+        self.input.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S> Source for LatencyProbe<S>
+where
+    S: Source,
+    S::Item: rodio::Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    #[test]
+    fn reports_nothing_before_the_first_press() {
+        assert_eq!(LatencyStats::default().report(), None);
+    }
+
+    #[test]
+    fn reports_average_and_maximum() {
+        let stats = LatencyStats::default();
+        stats.record(Duration::from_millis(2));
+        stats.record(Duration::from_millis(5));
+
+        assert_eq!(
+            stats.report().unwrap(),
+            "Trigger latency over 2 presses: 3.5 ms average, 5.0 ms max"
+        );
+    }
+
+    #[test]
+    fn the_probe_records_once_on_the_first_sample() {
+        let stats = Arc::new(LatencyStats::default());
+        let source = SamplesBuffer::new(1, 8000, vec![1i16, 2, 3]);
+        let probe = LatencyProbe::new(source, Instant::now(), stats.clone());
+
+        assert_eq!(probe.collect::<Vec<i16>>(), [1, 2, 3]);
+        assert_eq!(stats.count.load(Ordering::Relaxed), 1);
+    }
+}
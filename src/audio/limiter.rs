@@ -0,0 +1,245 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::Source;
+
+// About -1 dB, leaves a little room for the converters of the sound device
+const THRESHOLD: f32 = 0.89;
+
+// How fast the gain goes down on a peak, quick enough that the soft clipper only gets the
+// start of it
+const ATTACK: Duration = Duration::from_millis(1);
+// How fast the gain comes back up once the peaks are gone
+const RELEASE: Duration = Duration::from_millis(150);
+
+// Frames the gain reduction is reported over, about 20 ms at 48 kHz
+const REPORT_FRAMES: usize = 1024;
+
+/// How much the limiter turned the master down lately, readable from any thread.
+#[derive(Default)]
+pub struct GainReduction {
+    // f32 bits of the lowest gain of the last report window, 0 bits until anything was reported
+    gain: AtomicU32,
+}
+
+impl GainReduction {
+    /// The gain reduction in decibel, 0.0 while the limiter is idle.
+    pub fn decibel(&self) -> f32 {
+        let gain = f32::from_bits(self.gain.load(Ordering::Relaxed));
+
+        if gain <= 0.0 || gain >= 1.0 {
+            0.0
+        } else {
+            -20.0 * gain.log10()
+        }
+    }
+
+    fn publish(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Keeps the master mix below full scale, so stacked loops at high volume don't clip.
+///
+/// The gain goes down over `ATTACK` when a frame would go above the threshold and comes back
+/// up over `RELEASE`. What gets past during the attack is rounded off by a soft clipper instead
+/// of clipping hard at full scale.
+pub struct Limiter<S> {
+    input: S,
+    reduction: Arc<GainReduction>,
+
+    gain: f32,
+    // Per frame, so the timing doesn't depend on the sample rate
+    attack_factor: f32,
+    release_step: f32,
+
+    frame: Vec<f32>,
+    frame_position: usize,
+
+    lowest_gain: f32,
+    report_frames: usize,
+}
+
+impl<S> Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, reduction: Arc<GainReduction>) -> Limiter<S> {
+        let attack_frames = ATTACK.as_secs_f32() * input.sample_rate() as f32;
+        let release_frames = RELEASE.as_secs_f32() * input.sample_rate() as f32;
+
+        Limiter {
+            input,
+            reduction,
+            gain: 1.0,
+            attack_factor: 1.0 - (-1.0 / attack_frames.max(1.0)).exp(),
+            release_step: 1.0 / release_frames.max(1.0),
+            frame: vec![],
+            frame_position: 0,
+            lowest_gain: 1.0,
+            report_frames: 0,
+        }
+    }
+
+    /// Reads the next frame and works out its gain, returns false once the input ended.
+    fn next_frame(&mut self) -> bool {
+        let channels = self.input.channels().max(1) as usize;
+
+        self.frame.clear();
+        self.frame.extend(self.input.by_ref().take(channels));
+        self.frame_position = 0;
+
+        if self.frame.is_empty() {
+            return false;
+        }
+
+        let peak = self
+            .frame
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+
+        if peak * self.gain > THRESHOLD {
+            let target = THRESHOLD / peak;
+            self.gain += (target - self.gain) * self.attack_factor;
+        } else {
+            self.gain = (self.gain + self.release_step).min(1.0);
+        }
+
+        for sample in &mut self.frame {
+            *sample = soft_clip(*sample * self.gain);
+        }
+
+        self.lowest_gain = self.lowest_gain.min(self.gain);
+        self.report_frames += 1;
+
+        if self.report_frames >= REPORT_FRAMES {
+            self.reduction.publish(self.lowest_gain);
+            self.lowest_gain = 1.0;
+            self.report_frames = 0;
+        }
+
+        true
+    }
+}
+
+/// Passes everything up to the threshold and bends the rest smoothly towards full scale.
+fn soft_clip(sample: f32) -> f32 {
+    let level = sample.abs();
+
+    if level <= THRESHOLD {
+        return sample;
+    }
+
+    let headroom = 1.0 - THRESHOLD;
+    let over = (level - THRESHOLD) / headroom;
+
+    (THRESHOLD + headroom * over.tanh()).copysign(sample)
+}
+
+impl<S> Iterator for Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.frame_position >= self.frame.len() && !self.next_frame() {
+            return None;
+        }
+
+        let sample = self.frame[self.frame_position];
+        self.frame_position += 1;
+
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S> Source for Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// A second of a stereo 440 Hz sine at `amplitude`.
+    fn sine(amplitude: f32) -> SamplesBuffer<f32> {
+        let samples = (0..SAMPLE_RATE)
+            .map(|frame| amplitude * (TAU * 440.0 * frame as f32 / SAMPLE_RATE as f32).sin())
+            .flat_map(|sample| [sample, sample])
+            .collect::<Vec<f32>>();
+
+        SamplesBuffer::new(2, SAMPLE_RATE, samples)
+    }
+
+    #[test]
+    fn loud_input_never_goes_above_full_scale() {
+        let reduction = Arc::new(GainReduction::default());
+        let output: Vec<f32> = Limiter::new(sine(4.0), reduction.clone()).collect();
+
+        assert_eq!(output.len(), 2 * SAMPLE_RATE as usize);
+        assert!(output.iter().all(|sample| sample.abs() <= 1.0));
+
+        // Without the gain coming down the soft clipper would sit at full scale, once the attack
+        // is over only the overshoot of each peak is left for it
+        let settled = &output[2 * SAMPLE_RATE as usize / 100..];
+        assert!(settled.iter().all(|sample| sample.abs() < 0.99));
+
+        // 4.0 down to the threshold is about 13 dB
+        assert!(reduction.decibel() > 12.0);
+    }
+
+    #[test]
+    fn quiet_input_passes_unchanged() {
+        let reduction = Arc::new(GainReduction::default());
+        let output = Limiter::new(sine(0.5), reduction.clone());
+
+        assert!(output
+            .zip(sine(0.5))
+            .all(|(limited, input)| limited == input));
+        assert_eq!(reduction.decibel(), 0.0);
+    }
+
+    #[test]
+    fn soft_clip_stays_below_full_scale() {
+        for level in [0.0, 0.5, THRESHOLD, 0.95, 1.0, 2.0, 100.0] {
+            let clipped = soft_clip(level);
+            assert!(clipped <= 1.0, "{} clipped to {}", level, clipped);
+            assert_eq!(soft_clip(-level), -clipped);
+        }
+
+        assert_eq!(soft_clip(0.5), 0.5);
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::{Sample, Source};
+
+// Samples a level is taken over, about 10 ms of stereo audio at 48 kHz
+const METER_WINDOW: usize = 1024;
+
+// Quietest level a meter shows, in decibel below full scale
+const METER_RANGE_DB: f32 = 60.0;
+
+/// How far a meter is filled for `level` (0.0 to 1.0), in even decibel steps like on a desk.
+pub fn meter_fill(level: f32) -> f32 {
+    if level <= 0.0 {
+        return 0.0;
+    }
+
+    ((20.0 * level.log10() + METER_RANGE_DB) / METER_RANGE_DB).clamp(0.0, 1.0)
+}
+
+/// Level of the audio running through a `MeterTap`, readable from any thread.
+#[derive(Default)]
+pub struct LevelMeter {
+    // f32 bits of the levels of the last finished window
+    peak: AtomicU32,
+    rms: AtomicU32,
+}
+
+impl LevelMeter {
+    /// Highest sample of the last window, 1.0 is full scale.
+    pub fn peak(&self) -> f32 {
+        f32::from_bits(self.peak.load(Ordering::Relaxed))
+    }
+
+    /// Average level of the last window, closer to how loud it sounds than the peak.
+    pub fn rms(&self) -> f32 {
+        f32::from_bits(self.rms.load(Ordering::Relaxed))
+    }
+
+    fn publish(&self, peak: f32, rms: f32) {
+        self.peak.store(peak.to_bits(), Ordering::Relaxed);
+        self.rms.store(rms.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Passes a source through and measures it into a `LevelMeter`.
+pub struct MeterTap<S> {
+    input: S,
+    meter: Arc<LevelMeter>,
+
+    window_peak: f32,
+    window_squares: f32,
+    window_samples: usize,
+}
+
+impl<S> MeterTap<S> {
+    pub fn new(input: S, meter: Arc<LevelMeter>) -> MeterTap<S> {
+        MeterTap {
+            input,
+            meter,
+            window_peak: 0.0,
+            window_squares: 0.0,
+            window_samples: 0,
+        }
+    }
+}
+
+impl<S> Iterator for MeterTap<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(sample) = self.input.next() else {
+            // Nothing left to hear
+            self.meter.publish(0.0, 0.0);
+            return None;
+        };
+
+        let value = sample.to_f32();
+
+        self.window_peak = self.window_peak.max(value.abs());
+        self.window_squares += value * value;
+        self.window_samples += 1;
+
+        if self.window_samples >= METER_WINDOW {
+            let rms = (self.window_squares / self.window_samples as f32).sqrt();
+            self.meter.publish(self.window_peak, rms);
+
+            self.window_peak = 0.0;
+            self.window_squares = 0.0;
+            self.window_samples = 0;
+        }
+
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S> Source for MeterTap<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    /// Runs `samples` through a tap and returns its meter.
+    fn measure(samples: Vec<f32>) -> (Arc<LevelMeter>, MeterTap<SamplesBuffer<f32>>) {
+        let meter = Arc::new(LevelMeter::default());
+        let tap = MeterTap::new(SamplesBuffer::new(1, 48000, samples), meter.clone());
+
+        (meter, tap)
+    }
+
+    #[test]
+    fn measures_peak_and_average_per_window() {
+        // Half of the window at -0.5, the other half silent
+        let mut samples = vec![-0.5; METER_WINDOW / 2];
+        samples.resize(METER_WINDOW + 1, 0.0);
+        let (meter, mut tap) = measure(samples);
+
+        tap.by_ref().take(METER_WINDOW).for_each(drop);
+        assert_eq!(meter.peak(), 0.5);
+        assert!((meter.rms() - 0.5 * 0.5f32.sqrt()).abs() < 0.0001);
+
+        // The next window isn't finished, the last one stays
+        tap.next();
+        assert_eq!(meter.peak(), 0.5);
+    }
+
+    #[test]
+    fn drops_to_silence_when_the_sound_ends() {
+        let (meter, tap) = measure(vec![1.0; METER_WINDOW]);
+
+        assert_eq!(tap.count(), METER_WINDOW);
+        assert_eq!((meter.peak(), meter.rms()), (0.0, 0.0));
+    }
+
+    #[test]
+    fn meters_fill_in_decibel_down_to_60() {
+        assert_eq!(meter_fill(1.0), 1.0);
+        assert!((meter_fill(0.5) - 0.9).abs() < 0.001, "-6 dB");
+        assert!((meter_fill(0.001) - 0.0).abs() < 0.0001, "-60 dB");
+        assert_eq!(meter_fill(0.0001), 0.0);
+        assert_eq!(meter_fill(0.0), 0.0);
+        assert_eq!(meter_fill(2.0), 1.0);
+    }
+}
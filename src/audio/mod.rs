@@ -0,0 +1,10 @@
+pub mod bus;
+pub mod channel;
+pub mod ducking;
+pub mod envelope;
+pub mod fade;
+pub mod latency;
+pub mod limiter;
+pub mod meter;
+pub mod output;
+pub mod pcm;
@@ -0,0 +1,166 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use rodio::{dynamic_mixer::DynamicMixer, Source};
+
+use crate::{discovery::DeviceMatcher, MyError};
+
+pub const DEFAULT_CHANNELS: u16 = 2;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+const CHUNK_DURATION: Duration = Duration::from_millis(10);
+
+/// Where the mixed audio of the `SoundSystem` ends up.
+pub enum OutputBackend {
+    /// The first cpal output device the matcher accepts, the default device if there is none.
+    Device(DeviceMatcher),
+
+    /// Renders and discards the mix, at the given speed (1.0 is real time).
+    Null(f32),
+
+    /// Renders the mix into a 32 bit float WAV file, at the given speed (1.0 is real time).
+    WavFile(PathBuf, f32),
+}
+
+/// The master mix as a source that never ends.
+///
+/// The mixer itself ends as soon as nothing is playing, this keeps producing silence instead.
+pub struct MasterMix {
+    mixer: DynamicMixer<f32>,
+}
+
+impl MasterMix {
+    pub fn new(mixer: DynamicMixer<f32>) -> MasterMix {
+        MasterMix { mixer }
+    }
+}
+
+impl Iterator for MasterMix {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        Some(self.mixer.next().unwrap_or(0.0))
+    }
+}
+
+impl Source for MasterMix {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.mixer.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.mixer.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Pulls the master mix on a thread of its own instead of an audio device.
+pub struct OfflineOutput {
+    stop_flag: Arc<AtomicBool>,
+    render_thread: Option<JoinHandle<Result<(), hound::Error>>>,
+}
+
+impl OfflineOutput {
+    pub fn start<S>(
+        mut master: S,
+        wav_path: Option<PathBuf>,
+        speed: f32,
+    ) -> Result<OfflineOutput, MyError>
+    where
+        S: Source<Item = f32> + Send + 'static,
+    {
+        let channels = master.channels();
+        let sample_rate = master.sample_rate();
+
+        let mut writer = match wav_path {
+            Some(path) => Some(hound::WavWriter::create(
+                path,
+                hound::WavSpec {
+                    channels,
+                    sample_rate,
+                    bits_per_sample: 32,
+                    sample_format: hound::SampleFormat::Float,
+                },
+            )?),
+            None => None,
+        };
+
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread_stop_flag = Arc::clone(&stop_flag);
+
+        let samples_per_chunk =
+            (sample_rate as usize * channels as usize) * CHUNK_DURATION.as_millis() as usize / 1000;
+
+        let render_thread = thread::spawn(move || {
+            let start = Instant::now();
+            let mut rendered = Duration::ZERO;
+
+            while !thread_stop_flag.load(Ordering::SeqCst) {
+                for _ in 0..samples_per_chunk {
+                    let sample = master.next().unwrap_or(0.0);
+
+                    if let Some(writer) = &mut writer {
+                        writer.write_sample(sample)?;
+                    }
+                }
+
+                rendered += CHUNK_DURATION;
+
+                // Stay at the requested speed relative to the wall clock
+                let due = start + rendered.div_f32(speed);
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+            }
+
+            if let Some(writer) = writer {
+                writer.finalize()?;
+            }
+
+            Ok(())
+        });
+
+        Ok(OfflineOutput {
+            stop_flag,
+            render_thread: Some(render_thread),
+        })
+    }
+
+    /// Stops rendering and finishes the WAV file, if there is one.
+    pub fn stop(&mut self) -> Result<(), MyError> {
+        self.stop_flag.store(true, Ordering::SeqCst);
+
+        if let Some(render_thread) = self.render_thread.take() {
+            match render_thread.join() {
+                Ok(result) => result?,
+                Err(_) => return Err(MyError::SoundSystemError("Render thread panicked.")),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for OfflineOutput {
+    fn drop(&mut self) {
+        if let Err(err) = self.stop() {
+            println!("Could not stop audio rendering: {}", err);
+        }
+    }
+}
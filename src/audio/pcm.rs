@@ -0,0 +1,180 @@
+use std::{
+    io::{self, Read, Seek},
+    sync::Arc,
+    time::Duration,
+};
+
+use rodio::{Decoder, Source};
+
+/// Longest sound that is kept decoded, its samples take about ten times the memory of the file.
+pub const MAX_DURATION: Duration = Duration::from_secs(30);
+
+/// A whole sound decoded to samples, shared by all its playbacks.
+pub struct PcmData {
+    samples: Vec<i16>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl PcmData {
+    /// Decodes the whole sound, decoding stops with an error once it is past `MAX_DURATION`.
+    pub fn decode<R>(reader: R) -> io::Result<PcmData>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        let decoder = match Decoder::new(reader) {
+            Ok(value) => value,
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        let channels = decoder.channels();
+        let sample_rate = decoder.sample_rate();
+
+        let max_samples =
+            (MAX_DURATION.as_secs_f64() * sample_rate as f64) as usize * channels as usize;
+        let samples: Vec<i16> = decoder.take(max_samples + 1).collect();
+
+        if samples.len() > max_samples {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "longer than {} s, too long to predecode",
+                    MAX_DURATION.as_secs()
+                ),
+            ));
+        }
+
+        Ok(PcmData {
+            samples,
+            channels,
+            sample_rate,
+        })
+    }
+
+    pub fn duration(&self) -> Duration {
+        let frames = self.samples.len() / self.channels.max(1) as usize;
+
+        Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+}
+
+/// Plays `PcmData` without any decoding, so it starts right away.
+pub struct PcmSource {
+    data: Arc<PcmData>,
+    position: usize,
+    looped: bool,
+}
+
+impl PcmSource {
+    pub fn new(data: Arc<PcmData>, looped: bool) -> PcmSource {
+        PcmSource {
+            data,
+            position: 0,
+            looped,
+        }
+    }
+}
+
+impl Iterator for PcmSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.position >= self.data.samples.len() {
+            if !self.looped || self.data.samples.is_empty() {
+                return None;
+            }
+
+            self.position = 0;
+        }
+
+        let sample = self.data.samples[self.position];
+        self.position += 1;
+
+        Some(sample)
+    }
+}
+
+impl Source for PcmSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.data.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.data.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        if self.looped {
+            None
+        } else {
+            Some(self.data.duration())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::BufReader, path::PathBuf};
+
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn pcm(samples: &[i16]) -> Arc<PcmData> {
+        Arc::new(PcmData {
+            samples: samples.to_vec(),
+            channels: 2,
+            sample_rate: 4,
+        })
+    }
+
+    #[test]
+    fn decodes_the_whole_file() {
+        let file = BufReader::new(File::open("assets/testsound.wav").unwrap());
+        let data = PcmData::decode(file).unwrap();
+
+        assert_eq!(data.channels, 2);
+        assert_eq!(data.sample_rate, 44100);
+        assert_eq!(data.duration(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn refuses_sounds_longer_than_the_limit() {
+        let dir = TestDir::new("pcm_limit");
+        let decode = |path: PathBuf| PcmData::decode(BufReader::new(File::open(path).unwrap()));
+
+        assert!(decode(dir.add_silence("long.wav", 31)).is_err());
+        assert_eq!(
+            decode(dir.add_silence("limit.wav", 30)).unwrap().duration(),
+            MAX_DURATION
+        );
+    }
+
+    #[test]
+    fn plays_the_samples_once() {
+        let source = PcmSource::new(pcm(&[1, 2, 3, 4]), false);
+
+        assert_eq!(source.total_duration(), Some(Duration::from_millis(500)));
+        assert_eq!(source.collect::<Vec<i16>>(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn loops_back_to_the_start() {
+        let source = PcmSource::new(pcm(&[1, 2, 3, 4]), true);
+
+        assert_eq!(source.total_duration(), None);
+        assert_eq!(
+            source.take(10).collect::<Vec<i16>>(),
+            [1, 2, 3, 4, 1, 2, 3, 4, 1, 2]
+        );
+    }
+
+    #[test]
+    fn an_empty_loop_ends() {
+        let mut source = PcmSource::new(pcm(&[]), true);
+
+        assert_eq!(source.next(), None);
+    }
+}
@@ -2,11 +2,10 @@ use std::{
     collections::HashMap,
     fs::File,
     io::Read,
+    path::Path,
     sync::{Arc, Mutex},
 };
 
-use push2_display::Push2Display;
-
 #[rustfmt::skip]
 mod unformatted {
 
@@ -50,14 +49,20 @@ mod unformatted {
         Control29,
         Control20,
         Control21,
+        Control22,
         Control24,
         Control25,
+        Control44, // Left arrow
+        Control45, // Right arrow
+        Control62, // "Page <"
+        Control63, // "Page >"
     }
 }
 
 use crate::{
-    device_modes::{sound_mode::SoundMode, DeviceMode, LightAction},
-    midi::MidiConnection,
+    device_modes::{mixer_mode::MixerMode, sound_mode::SoundMode, DeviceMode, LightAction},
+    frame_buffer::FrameBuffer,
+    midi::SharedMidi,
     sound_system::SoundSystem,
     MyError,
 };
@@ -67,6 +72,10 @@ use crate::device_modes::spotify_mode::SpotifyMode;
 
 pub use unformatted::{ButtonType, ControlName, EncoderName, NoteName};
 
+// Where the modes are in `ButtonMap::device_modes`, the sound mode comes first
+const MIXER_MODE: usize = 1;
+const SPOTIFY_MODE: usize = 2;
+
 pub struct ButtonMap {
     button_values: HashMap<u8, ButtonType>,
 
@@ -77,9 +86,28 @@ pub struct ButtonMap {
 impl ButtonMap {
     pub fn new(
         sound_system: Arc<Mutex<SoundSystem>>,
-        midiconn: &Arc<Mutex<MidiConnection>>,
+        button_values_path: &Path,
+        board_path: &Path,
+        midiconn: &SharedMidi,
+    ) -> Result<ButtonMap, MyError> {
+        let device_modes: Vec<Box<dyn DeviceMode>> = vec![
+            Box::new(SoundMode::new(Arc::clone(&sound_system), board_path)?),
+            Box::new(MixerMode::new(sound_system)),
+            #[cfg(feature = "spotify")]
+            Box::new(SpotifyMode::new()?),
+        ];
+
+        ButtonMap::with_modes(button_values_path, device_modes, midiconn)
+    }
+
+    /// A button map switching between the given modes, which have to be in the order of
+    /// `MIXER_MODE` and `SPOTIFY_MODE` after the sound mode.
+    pub fn with_modes(
+        button_values_path: &Path,
+        mut device_modes: Vec<Box<dyn DeviceMode>>,
+        midiconn: &SharedMidi,
     ) -> Result<ButtonMap, MyError> {
-        let file = File::open("config/buttonvalues.ron");
+        let file = File::open(button_values_path);
 
         let mut file = match file {
             Ok(handle) => handle,
@@ -97,16 +125,10 @@ impl ButtonMap {
         let button_values: HashMap<u8, ButtonType> =
             ron::de::from_str(&config_string).expect("Could not deserialize SoundConfig.");
 
-        let mut device_modes: Vec<Box<dyn DeviceMode>> = Vec::new();
-        device_modes.push(Box::new(SoundMode::new(sound_system)?));
-
-        #[cfg(feature = "spotify")]
-        device_modes.push(Box::new(SpotifyMode::new()?));
-
         device_modes[0].apply_button_lights(midiconn, &button_values)?;
 
         Ok(ButtonMap {
-            button_values: button_values,
+            button_values,
             device_modes,
             current_mode: 0_usize,
         })
@@ -116,7 +138,7 @@ impl ButtonMap {
         &mut self,
         address: u8,
         change: i16,
-        midiconn: &Arc<Mutex<MidiConnection>>,
+        midiconn: &SharedMidi,
     ) -> Result<(), MyError> {
         let mut light_action = LightAction::None;
 
@@ -128,10 +150,13 @@ impl ButtonMap {
                         self.current_mode = 0;
                         control_change = true;
                     } else if *control_name == ControlName::Control21 {
-                        if self.device_modes.len() > 1 {
-                            self.current_mode = 1;
+                        if self.device_modes.len() > SPOTIFY_MODE {
+                            self.current_mode = SPOTIFY_MODE;
                         }
 
+                        control_change = true;
+                    } else if *control_name == ControlName::Control22 {
+                        self.current_mode = MIXER_MODE;
                         control_change = true;
                     }
 
@@ -146,8 +171,6 @@ impl ButtonMap {
                     light_action = self.device_modes[self.current_mode].button_press(*note_name)?;
                 }
                 ButtonType::Encoder(encoder_name) => {
-                    
-                    println!("Got encoder change: {:?} - {:?}", encoder_name, change);
                     light_action = self.device_modes[self.current_mode]
                         .encoder_change(*encoder_name, change)?;
                 }
@@ -170,7 +193,14 @@ impl ButtonMap {
         Ok(())
     }
 
-    pub fn update(&mut self, midiconn: &mut Arc<Mutex<MidiConnection>>) -> Result<(), MyError> {
+    pub fn update(&mut self, midiconn: &SharedMidi) -> Result<(), MyError> {
+        // e.g. the sound mode fading and ducking its sounds while the mixer is shown
+        for (index, mode) in self.device_modes.iter_mut().enumerate() {
+            if index != self.current_mode {
+                mode.background_update()?;
+            }
+        }
+
         let light_action: LightAction = self.device_modes[self.current_mode].update()?;
 
         match light_action {
@@ -189,10 +219,11 @@ impl ButtonMap {
         Ok(())
     }
 
-    pub fn clear_button_lights(
-        &mut self,
-        midiconn: &Arc<Mutex<MidiConnection>>,
-    ) -> Result<(), MyError> {
+    pub fn is_loading(&self) -> bool {
+        self.device_modes.iter().any(|mode| mode.is_loading())
+    }
+
+    pub fn clear_button_lights(&mut self, midiconn: &SharedMidi) -> Result<(), MyError> {
         let mutex_guard = midiconn.try_lock();
 
         let mut mutex_guard = match mutex_guard {
@@ -217,19 +248,203 @@ impl ButtonMap {
         Ok(())
     }
 
-    pub fn apply_button_lights(
-        &mut self,
-        midiconn: &Arc<Mutex<MidiConnection>>,
-    ) -> Result<(), MyError> {
+    pub fn apply_button_lights(&mut self, midiconn: &SharedMidi) -> Result<(), MyError> {
         // let current device-mode update the lights
         self.device_modes[self.current_mode].apply_button_lights(midiconn, &self.button_values)?;
 
         Ok(())
     }
 
-    pub fn display(&self, display: &mut Push2Display) -> Result<(), MyError> {
+    pub fn display(&self, display: &mut FrameBuffer) -> Result<(), MyError> {
         self.device_modes[self.current_mode].display(display)?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        audio::output::OutputBackend,
+        midi::{VirtualMidi, CONTROL_CHANGE, NOTE_ON},
+    };
+
+    const PAD_0X0: u8 = 36;
+    const PAD_1X0: u8 = 37;
+    const PAD_2X0: u8 = 38;
+    const SOUND_MODE_BUTTON: u8 = 20;
+    const MIXER_MODE_BUTTON: u8 = 22;
+
+    /// The test board on a silent output, with every sound loaded.
+    pub(crate) fn test_board() -> (ButtonMap, Arc<Mutex<VirtualMidi>>, SharedMidi) {
+        let sound_system = Arc::new(Mutex::new(
+            SoundSystem::new(&OutputBackend::Null(1.0)).unwrap(),
+        ));
+        let virtual_midi = Arc::new(Mutex::new(VirtualMidi::new()));
+        let midiconn: SharedMidi = virtual_midi.clone();
+
+        let device_modes: Vec<Box<dyn DeviceMode>> = vec![
+            Box::new(
+                SoundMode::new(
+                    Arc::clone(&sound_system),
+                    Path::new("config/sound_config.ron"),
+                )
+                .unwrap(),
+            ),
+            Box::new(MixerMode::new(sound_system)),
+        ];
+
+        let mut button_map = ButtonMap::with_modes(
+            Path::new("config/buttonvalues.ron"),
+            device_modes,
+            &midiconn,
+        )
+        .unwrap();
+
+        while button_map.is_loading() {
+            button_map.update(&midiconn).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        button_map.apply_button_lights(&midiconn).unwrap();
+
+        (button_map, virtual_midi, midiconn)
+    }
+
+    /// Counts how often it was updated, shown and in the background.
+    #[derive(Default)]
+    struct CountingMode {
+        updates: Arc<AtomicUsize>,
+        background_updates: Arc<AtomicUsize>,
+    }
+
+    impl DeviceMode for CountingMode {
+        fn button_press(&mut self, _note_name: NoteName) -> Result<LightAction, MyError> {
+            Ok(LightAction::None)
+        }
+
+        fn control_press(&mut self, _control_name: ControlName) -> Result<LightAction, MyError> {
+            Ok(LightAction::None)
+        }
+
+        fn encoder_change(
+            &mut self,
+            _encoder_name: EncoderName,
+            _change: i16,
+        ) -> Result<LightAction, MyError> {
+            Ok(LightAction::None)
+        }
+
+        fn apply_button_lights(
+            &mut self,
+            _midiconn: &SharedMidi,
+            _button_values: &HashMap<u8, ButtonType>,
+        ) -> Result<(), MyError> {
+            Ok(())
+        }
+
+        fn update(&mut self) -> Result<LightAction, MyError> {
+            self.updates.fetch_add(1, Ordering::Relaxed);
+            Ok(LightAction::None)
+        }
+
+        fn background_update(&mut self) -> Result<(), MyError> {
+            self.background_updates.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn display(&self, _display: &mut FrameBuffer) -> Result<(), MyError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn hidden_modes_only_get_background_updates() {
+        let midiconn: SharedMidi = Arc::new(Mutex::new(VirtualMidi::new()));
+
+        let shown = CountingMode::default();
+        let hidden = CountingMode::default();
+        let counts = [
+            (shown.updates.clone(), shown.background_updates.clone()),
+            (hidden.updates.clone(), hidden.background_updates.clone()),
+        ];
+
+        let mut button_map = ButtonMap::with_modes(
+            Path::new("config/buttonvalues.ron"),
+            vec![Box::new(shown), Box::new(hidden)],
+            &midiconn,
+        )
+        .unwrap();
+
+        button_map.update(&midiconn).unwrap();
+        button_map.update(&midiconn).unwrap();
+
+        let [(shown_updates, shown_background), (hidden_updates, hidden_background)] =
+            counts.map(|(updates, background)| {
+                (
+                    updates.load(Ordering::Relaxed),
+                    background.load(Ordering::Relaxed),
+                )
+            });
+
+        assert_eq!((shown_updates, shown_background), (2, 0));
+        assert_eq!((hidden_updates, hidden_background), (0, 2));
+    }
+
+    #[test]
+    fn pads_show_the_state_of_their_sound() {
+        let (mut button_map, virtual_midi, midiconn) = test_board();
+
+        {
+            let midi = virtual_midi.lock().unwrap();
+            assert_eq!(midi.light(NOTE_ON, PAD_0X0), 56);
+            assert_eq!(midi.light(NOTE_ON, PAD_1X0), 125);
+            assert_eq!(midi.light(NOTE_ON, PAD_2X0), 0);
+        }
+
+        button_map.activate_button(PAD_1X0, 127, &midiconn).unwrap();
+        assert_eq!(virtual_midi.lock().unwrap().light(NOTE_ON, PAD_1X0), 127);
+
+        // Pressing a looping sound again fades it out, it looks idle right away
+        button_map.activate_button(PAD_1X0, 127, &midiconn).unwrap();
+        assert_eq!(virtual_midi.lock().unwrap().light(NOTE_ON, PAD_1X0), 125);
+    }
+
+    #[test]
+    fn unmapped_pads_stay_dark() {
+        let (mut button_map, virtual_midi, midiconn) = test_board();
+
+        button_map.activate_button(PAD_2X0, 127, &midiconn).unwrap();
+
+        assert_eq!(virtual_midi.lock().unwrap().light(NOTE_ON, PAD_2X0), 0);
+    }
+
+    #[test]
+    fn mode_buttons_switch_the_lights() {
+        let (mut button_map, virtual_midi, midiconn) = test_board();
+
+        button_map
+            .activate_button(MIXER_MODE_BUTTON, 127, &midiconn)
+            .unwrap();
+        {
+            let midi = virtual_midi.lock().unwrap();
+            assert_eq!(midi.light(CONTROL_CHANGE, MIXER_MODE_BUTTON), 122);
+            assert_eq!(midi.light(NOTE_ON, PAD_1X0), 0);
+        }
+
+        button_map
+            .activate_button(SOUND_MODE_BUTTON, 127, &midiconn)
+            .unwrap();
+        {
+            let midi = virtual_midi.lock().unwrap();
+            assert_eq!(midi.light(CONTROL_CHANGE, MIXER_MODE_BUTTON), 123);
+            assert_eq!(midi.light(NOTE_ON, PAD_1X0), 125);
+        }
+    }
+}
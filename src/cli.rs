@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+/// Use the Ableton Push2 as a soundboard.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Directory containing devices.ron and buttonvalues.ron
+    #[arg(long, default_value = "config")]
+    pub config_dir: PathBuf,
+
+    /// Sound board file [default: <CONFIG_DIR>/sound_config.ron]
+    #[arg(long)]
+    pub board: Option<PathBuf>,
+
+    /// Audio output device, overrides devices.ron
+    #[arg(long)]
+    pub sound_device: Option<String>,
+
+    /// Push2 midi input port, overrides devices.ron
+    #[arg(long)]
+    pub midi_in: Option<String>,
+
+    /// Push2 midi output port, overrides devices.ron
+    #[arg(long)]
+    pub midi_out: Option<String>,
+
+    /// Print the available midi ports and audio output devices and exit
+    #[arg(long)]
+    pub list_devices: bool,
+
+    /// Show a simulator window instead of using the Push2
+    #[arg(long)]
+    pub simulate: bool,
+
+    /// Record all midi input and output into a session file
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Replay a recorded session and check the lights match the recording
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["record", "simulate"])]
+    pub replay: Option<PathBuf>,
+
+    /// Discard the audio instead of playing it on a device
+    #[arg(long)]
+    pub null_audio: bool,
+
+    /// Render the audio into a WAV file instead of playing it on a device
+    #[arg(long, value_name = "FILE", conflicts_with = "null_audio")]
+    pub render_wav: Option<PathBuf>,
+
+    /// Speed of --null-audio and --render-wav relative to real time
+    #[arg(long, default_value_t = 1.0, value_parser = parse_render_speed)]
+    pub render_speed: f32,
+}
+
+/// The offline output divides its buffer time by the speed, so it has to be above zero.
+fn parse_render_speed(value: &str) -> Result<f32, String> {
+    let speed: f32 = value
+        .parse()
+        .map_err(|_| format!("\"{}\" is not a number", value))?;
+
+    if speed.is_finite() && speed > 0.0 {
+        Ok(speed)
+    } else {
+        Err(String::from("must be above 0"))
+    }
+}
+
+impl Cli {
+    pub fn device_config_path(&self) -> PathBuf {
+        self.config_dir.join("devices.ron")
+    }
+
+    pub fn button_values_path(&self) -> PathBuf {
+        self.config_dir.join("buttonvalues.ron")
+    }
+
+    pub fn board_path(&self) -> PathBuf {
+        match &self.board {
+            Some(board) => board.clone(),
+            None => self.config_dir.join("sound_config.ron"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_speed_has_to_be_above_zero() {
+        for speed in ["0", "-1", "NaN", "fast"] {
+            assert!(
+                Cli::try_parse_from(["push2_soundboard", "--render-speed", speed]).is_err(),
+                "accepted --render-speed {}",
+                speed
+            );
+        }
+
+        let cli = Cli::try_parse_from(["push2_soundboard", "--render-speed", "0.5"]).unwrap();
+        assert_eq!(cli.render_speed, 0.5);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use embedded_graphics::{
+    mono_font::{iso_8859_13::FONT_10X20, MonoTextStyle},
+    pixelcolor::Bgr565,
+    prelude::{DrawTarget, Point, RgbColor, Size},
+    primitives::{Primitive, PrimitiveStyle, Rectangle},
+    text::Text,
+    Drawable,
+};
+
+use crate::{
+    audio::{channel::Channel, meter::meter_fill},
+    button_map::{ButtonType, ControlName, EncoderName, NoteName},
+    frame_buffer::FrameBuffer,
+    lock_or_return_err,
+    sound_system::SoundSystem,
+    MyError, DEFAULT_VOLUME, MAX_VOLUME,
+};
+
+use super::{sound_mode::SoundMode, LightAction};
+
+// One column per encoder above the display, the last one stays the master volume
+const CHANNEL_COUNT: usize = 7;
+const COLUMN_WIDTH: i32 = 120;
+const MAX_NAME_LENGTH: usize = 11;
+
+const BAR_Y: i32 = 30;
+const BAR_HEIGHT: u32 = 100;
+const BAR_WIDTH: u32 = 20;
+
+/// Binds the first seven encoders above the display to the sounds playing right now.
+///
+/// Every playing sound gets a column with its name, level and trim, the encoder above it turns
+/// the trim. Sounds are bound in the order they started, so the columns shift left as sounds end.
+/// The last encoder and column are the master volume, as on the pads.
+pub struct MixerMode {
+    sound_system: Arc<Mutex<SoundSystem>>,
+
+    // Bound to the encoders from the left, taken on every update
+    channels: Vec<Channel>,
+}
+
+impl MixerMode {
+    pub fn new(sound_system: Arc<Mutex<SoundSystem>>) -> MixerMode {
+        MixerMode {
+            sound_system,
+            channels: vec![],
+        }
+    }
+
+    fn display_channels<D>(&self, display: &mut D) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
+        if self.channels.is_empty() {
+            Text::new(
+                "No sounds playing",
+                Point { x: 10, y: 15 },
+                MonoTextStyle::new(&FONT_10X20, Bgr565::WHITE),
+            )
+            .draw(display)?;
+
+            return Ok(());
+        }
+
+        for (index, channel) in self.channels.iter().enumerate() {
+            let x = index as i32 * COLUMN_WIDTH + 10;
+
+            Text::new(
+                &channel
+                    .name()
+                    .chars()
+                    .take(MAX_NAME_LENGTH)
+                    .collect::<String>(),
+                Point { x, y: 15 },
+                MonoTextStyle::new(&FONT_10X20, Bgr565::WHITE),
+            )
+            .draw(display)?;
+
+            let level = meter_fill(channel.meter().peak());
+            MixerMode::draw_bar(display, x, level, Bgr565::GREEN)?;
+
+            let trim = channel.trim_factor() / (MAX_VOLUME as f32 / DEFAULT_VOLUME as f32);
+            MixerMode::draw_bar(display, x + BAR_WIDTH as i32 + 10, trim, Bgr565::WHITE)?;
+
+            Text::new(
+                &format!("trim {:.0}%", channel.trim_factor() * 100.0),
+                Point { x, y: 150 },
+                MonoTextStyle::new(&FONT_10X20, Bgr565::WHITE),
+            )
+            .draw(display)?;
+        }
+
+        Ok(())
+    }
+
+    /// A vertical bar filled to `fill` (0.0 to 1.0) from the bottom.
+    fn draw_bar<D>(display: &mut D, x: i32, fill: f32, color: Bgr565) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
+        // Outline
+        Rectangle::new(
+            Point { x, y: BAR_Y },
+            Size {
+                width: BAR_WIDTH,
+                height: BAR_HEIGHT,
+            },
+        )
+        .into_styled(PrimitiveStyle::with_stroke(color, 1))
+        .draw(display)?;
+
+        Rectangle::new(
+            Point {
+                x,
+                y: ((BAR_Y as f32) + (1.0 - fill) * (BAR_HEIGHT as f32)) as i32,
+            },
+            Size {
+                width: BAR_WIDTH,
+                height: ((BAR_HEIGHT as f32) * fill) as u32,
+            },
+        )
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(display)?;
+
+        Ok(())
+    }
+}
+
+/// The encoder above the display a column belongs to, from the left.
+fn encoder_index(encoder_name: EncoderName) -> Option<usize> {
+    match encoder_name {
+        EncoderName::Control71 => Some(0),
+        EncoderName::Control72 => Some(1),
+        EncoderName::Control73 => Some(2),
+        EncoderName::Control74 => Some(3),
+        EncoderName::Control75 => Some(4),
+        EncoderName::Control76 => Some(5),
+        EncoderName::Control77 => Some(6),
+        _ => None,
+    }
+}
+
+impl super::DeviceMode for MixerMode {
+    fn button_press(&mut self, _note_name: NoteName) -> Result<LightAction, MyError> {
+        Ok(LightAction::None)
+    }
+
+    fn control_press(&mut self, _control_name: ControlName) -> Result<LightAction, MyError> {
+        Ok(LightAction::None)
+    }
+
+    fn encoder_change(
+        &mut self,
+        encoder_name: EncoderName,
+        change: i16,
+    ) -> Result<LightAction, MyError> {
+        if encoder_name == EncoderName::Control78 {
+            let sound_system = &self.sound_system;
+            lock_or_return_err!(sound_system).change_volume(change);
+
+            return Ok(LightAction::None);
+        }
+
+        if let Some(channel) =
+            encoder_index(encoder_name).and_then(|index| self.channels.get(index))
+        {
+            channel.change_trim(change);
+        }
+
+        Ok(LightAction::None)
+    }
+
+    fn apply_button_lights(
+        &mut self,
+        midiconn: &crate::midi::SharedMidi,
+        button_values: &HashMap<u8, ButtonType>,
+    ) -> Result<(), MyError> {
+        let mut mutex_guard = midiconn.try_lock().expect("Couldn't lock MidiConnection");
+
+        for (address, name) in button_values {
+            if *name == ButtonType::Encoder(EncoderName::Control78) {
+                mutex_guard.send_to_device(&[0b10110000, *address, 122u8])?;
+            }
+
+            if let ButtonType::ControlChange(control_name) = name {
+                match control_name {
+                    ControlName::Control20 => {
+                        mutex_guard.send_to_device(&[0b10110000, *address, 125u8])?;
+                    }
+                    ControlName::Control21 => {
+                        mutex_guard.send_to_device(&[0b10110000, *address, 123u8])?;
+                    }
+                    // The mode shown right now
+                    ControlName::Control22 => {
+                        mutex_guard.send_to_device(&[0b10110000, *address, 122u8])?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self) -> Result<LightAction, MyError> {
+        let sound_system = &self.sound_system;
+        let mut channels = lock_or_return_err!(sound_system).playing_channels();
+        channels.truncate(CHANNEL_COUNT);
+
+        self.channels = channels;
+
+        Ok(LightAction::None)
+    }
+
+    fn display(&self, display: &mut FrameBuffer) -> Result<(), MyError> {
+        self.display_channels(display)?;
+        SoundMode::draw_volume(&self.sound_system, display)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio::output::OutputBackend,
+        device_modes::DeviceMode,
+        midi::{MidiMessage, CONTROL_CHANGE},
+    };
+
+    /// Turns the encoder at `address` the way the Push2 reports it.
+    fn turn(mode: &mut MixerMode, address: u8, value: u8, encoder_name: EncoderName) {
+        let Some(MidiMessage::Input(parsed, change)) =
+            MidiMessage::parse(&[CONTROL_CHANGE, address, value])
+        else {
+            panic!("{} {} was not parsed", address, value);
+        };
+        assert_eq!(parsed, address);
+
+        mode.encoder_change(encoder_name, change).unwrap();
+    }
+
+    #[test]
+    fn encoders_turn_the_trim_of_their_column() {
+        let sound_system = Arc::new(Mutex::new(
+            SoundSystem::new(&OutputBackend::Null(1.0)).unwrap(),
+        ));
+        let mut mode = MixerMode::new(sound_system);
+
+        let first = Channel::new("first".to_string());
+        let second = Channel::new("second".to_string());
+        mode.channels = vec![first.clone(), second.clone()];
+
+        let step = 1.0 / DEFAULT_VOLUME as f32;
+
+        // 127 is one step to the left
+        turn(&mut mode, 72, 127, EncoderName::Control72);
+        assert!((second.trim_factor() - (1.0 - step)).abs() < 1e-6);
+
+        // 1 is one step to the right
+        turn(&mut mode, 72, 1, EncoderName::Control72);
+        turn(&mut mode, 72, 1, EncoderName::Control72);
+        assert!((second.trim_factor() - (1.0 + step)).abs() < 1e-6);
+
+        assert_eq!(first.trim_factor(), 1.0);
+
+        // No sound in that column
+        turn(&mut mode, 73, 1, EncoderName::Control73);
+    }
+
+    #[test]
+    fn the_last_encoder_stays_the_master_volume() {
+        let sound_system = Arc::new(Mutex::new(
+            SoundSystem::new(&OutputBackend::Null(1.0)).unwrap(),
+        ));
+        let mut mode = MixerMode::new(sound_system.clone());
+
+        let channels: Vec<Channel> = (0..CHANNEL_COUNT)
+            .map(|index| Channel::new(index.to_string()))
+            .collect();
+        mode.channels = channels.clone();
+
+        turn(&mut mode, 78, 1, EncoderName::Control78);
+
+        assert_eq!(
+            sound_system.lock().unwrap().get_volume_factor(),
+            (DEFAULT_VOLUME + 1) as f32 / DEFAULT_VOLUME as f32
+        );
+        assert!(channels.iter().all(|channel| channel.trim_factor() == 1.0));
+    }
+}
@@ -1,29 +1,26 @@
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
-};
-
-use push2_display::Push2Display;
+use std::collections::HashMap;
 
 use crate::{
     button_map::{ButtonType, ControlName, EncoderName, NoteName},
-    midi::MidiConnection,
+    frame_buffer::FrameBuffer,
+    midi::SharedMidi,
     MyError,
 };
 
+pub mod mixer_mode;
 pub mod sound_mode;
 
 #[cfg(feature = "spotify")]
 pub mod spotify_mode;
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug)]
 pub enum LightAction {
     None,
     Reapply,
     ClearAndReapply,
 }
 
-pub trait DeviceMode {
+pub trait DeviceMode: Send {
     fn button_press(&mut self, note_name: NoteName) -> Result<LightAction, MyError>;
 
     fn control_press(&mut self, control_name: ControlName) -> Result<LightAction, MyError>;
@@ -36,11 +33,22 @@ pub trait DeviceMode {
 
     fn apply_button_lights(
         &mut self,
-        midiconn: &Arc<Mutex<MidiConnection>>,
+        midiconn: &SharedMidi,
         button_values: &HashMap<u8, ButtonType>,
     ) -> Result<(), MyError>;
 
     fn update(&mut self) -> Result<LightAction, MyError>;
 
-    fn display(&self, display: &mut Push2Display) -> Result<(), MyError>;
+    /// Called instead of `update` while another mode is shown, for work that can't wait until
+    /// the mode is shown again.
+    fn background_update(&mut self) -> Result<(), MyError> {
+        Ok(())
+    }
+
+    fn display(&self, display: &mut FrameBuffer) -> Result<(), MyError>;
+
+    /// Whether the mode is still loading in the background, e.g. the sounds of its board.
+    fn is_loading(&self) -> bool {
+        false
+    }
 }
@@ -1,9 +1,7 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     default,
-    fs::File,
-    io::Read,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{
         mpsc::{channel, Receiver},
         Arc, Mutex,
@@ -12,76 +10,137 @@ use std::{
 };
 
 use embedded_graphics::{
-    mono_font::{iso_8859_13::FONT_10X20, MonoTextStyle},
+    mono_font::{
+        iso_8859_13::{FONT_10X20, FONT_6X10},
+        MonoTextStyle,
+    },
     pixelcolor::Bgr565,
-    prelude::{Point, RgbColor, Size},
+    prelude::{DrawTarget, Point, RgbColor, Size},
     primitives::{Primitive, PrimitiveStyle, Rectangle},
     text::Text,
     Drawable,
 };
 use notify_debouncer_full::{
     new_debouncer,
-    notify::{ReadDirectoryChangesWatcher, RecursiveMode, Watcher},
-    DebounceEventResult, DebouncedEvent, Debouncer, FileIdMap,
+    notify::{RecommendedWatcher, RecursiveMode},
+    DebounceEventResult, DebouncedEvent, Debouncer, RecommendedCache,
 };
-use push2_display::Push2Display;
 
 use crate::{
-    actions::{command::Command, sound::Sound, Action, ActionConfig, ActionState},
+    actions::{
+        command::Command,
+        loader::{SoundLoad, SoundLoader},
+        scene::Scene,
+        sound::{sound_name, SoundSettings},
+        validation,
+        variation::is_audio_file,
+        Action, ActionConfig, ActionSlot, ActionState,
+    },
+    audio::meter::{meter_fill, LevelMeter},
     button_map::{ButtonType, ControlName, EncoderName, NoteName},
+    frame_buffer::FrameBuffer,
+    lock_or_return_err,
     sound_system::SoundSystem,
     MyError, DEFAULT_VOLUME, MAX_VOLUME,
 };
 
 use super::LightAction;
 
-#[derive(Deserialize)]
-struct ActionConfigs {
-    actions: Vec<ActionConfig>,
+// Pads whose sound is still loading, a dark grey
+const LOADING_COLOR: u8 = 124;
+
+// The bus volumes end left of the master volume labels, one column per bus
+const BUS_AREA_RIGHT: i32 = 820;
+const BUS_COLUMN_WIDTH: i32 = 32;
+
+// Width of a FONT_10X20 character
+const CHAR_WIDTH: i32 = 10;
+
+// Level a sound has to reach to light its pad up with `level_lights`, about -20 dB
+const LEVEL_LIGHT_THRESHOLD: f32 = 0.1;
+
+/// What creating an action gives, sounds still have to be loaded in the background.
+enum ActionLoad {
+    Ready(Action),
+    Sound(SoundLoad),
 }
 
 pub struct SoundMode {
-    button_actions: HashMap<ButtonType, Action>,
+    button_actions: HashMap<ActionSlot, Action>,
     sound_system: Arc<Mutex<SoundSystem>>,
     file_watcher: Option<Receiver<DebouncedEvent>>,
-    file_watcher_intern: Option<Debouncer<ReadDirectoryChangesWatcher, FileIdMap>>,
+    file_watcher_intern: Option<Debouncer<RecommendedWatcher, RecommendedCache>>,
+
+    board_path: PathBuf,
+    // The configs the current actions were created from, to find out what a reload changes
+    action_configs: HashMap<ActionSlot, ActionConfig>,
+    watched_dirs: HashSet<PathBuf>,
+
+    // Names of the board's pages in config order, the pads show the current one
+    pages: Vec<String>,
+    current_page: usize,
+
+    // Problems with the last board that was read, the board before it stays active meanwhile
+    config_errors: Vec<String>,
+
+    loader: SoundLoader,
+    // Slots whose sound is still loading, with the ticket of the load that counts
+    loading: HashMap<ActionSlot, u64>,
+    // Progress of the loads since nothing was loading, for the display
+    loads_queued: usize,
+    loads_done: usize,
+    // Sounds of the current board that failed to load, their pads keep what they had before
+    load_errors: Vec<String>,
+    // Slots still holding the action of an earlier board, as their new sound failed to load
+    outdated: HashSet<ActionSlot>,
+
+    // Whether playing pads follow the level of their sound, and the ones above the threshold
+    level_lights: bool,
+    loud_slots: HashSet<ActionSlot>,
 }
 
 impl SoundMode {
-    pub fn new(sound_system: Arc<Mutex<SoundSystem>>) -> Result<SoundMode, MyError> {
+    pub fn new(
+        sound_system: Arc<Mutex<SoundSystem>>,
+        board_path: &Path,
+    ) -> Result<SoundMode, MyError> {
         let mut sound_mode = SoundMode {
             button_actions: default::Default::default(),
             sound_system,
             file_watcher: None,
             file_watcher_intern: None,
+            board_path: board_path.to_path_buf(),
+            action_configs: default::Default::default(),
+            watched_dirs: default::Default::default(),
+            pages: vec![],
+            current_page: 0,
+            config_errors: vec![],
+            loader: SoundLoader::new()?,
+            loading: default::Default::default(),
+            loads_queued: 0,
+            loads_done: 0,
+            load_errors: vec![],
+            outdated: default::Default::default(),
+            level_lights: false,
+            loud_slots: default::Default::default(),
         };
 
-        sound_mode.read_config("config/testconfig.ron")?;
+        sound_mode.read_config(board_path)?;
 
         Ok(sound_mode)
     }
 
-    pub fn add_action(&mut self, button: ButtonType, action: Action) {
-        if !self.button_actions.contains_key(&button) {
-            self.button_actions.insert(button, action);
-        } else {
-            *self
-                .button_actions
-                .get_mut(&button)
-                .expect("Could not get hash-map entry despite previous check.") = action;
-        }
-    }
-
-    pub fn read_config(&mut self, path: &str) -> Result<(), MyError> {
-        let (_tx, rx) = channel();
+    pub fn read_config(&mut self, path: &Path) -> Result<(), MyError> {
+        let (tx, rx) = channel();
 
-        // Select recommended watcher for debouncer.
-        // Using a callback here, could also be a channel.
-        let mut debouncer = match new_debouncer(
+        let debouncer = match new_debouncer(
             Duration::from_secs(2),
             None,
-            |result: DebounceEventResult| match result {
-                Ok(events) => events.iter().for_each(|_event| {}),
+            move |result: DebounceEventResult| match result {
+                Ok(events) => events.into_iter().for_each(|event| {
+                    // The receiver is gone when the sound mode was dropped
+                    let _ = tx.send(event);
+                }),
                 Err(errors) => errors.iter().for_each(|error| println!("{error:?}")),
             },
         ) {
@@ -89,107 +148,538 @@ impl SoundMode {
             Err(_) => return Err(MyError::FileWatcher("Could not create debouncer.")),
         };
 
-        // Add a path to be watched. All files and directories at that path and
-        // below will be monitored for changes.
-        match debouncer
-            
-            .watch(Path::new("."), RecursiveMode::Recursive)
-        {
-            Ok(()) => (),
-            Err(_) => {
-                return Err(MyError::FileWatcher(
-                    "Could not add watch to debounced watcher.",
-                ))
+        self.file_watcher = Some(rx);
+        self.file_watcher_intern = Some(debouncer);
+
+        // Event paths are canonical, so the board path has to be as well to compare them
+        self.board_path = canonical_path(path);
+        self.action_configs.clear();
+        self.watched_dirs.clear();
+
+        self.read_config_impl(&HashSet::new())?;
+
+        Ok(())
+    }
+
+    /// Reads the board again and swaps in what changed if it has no errors.
+    ///
+    /// Actions with the same config as before are kept as they are, so their sounds keep playing.
+    /// Actions using one of `changed_assets` are reloaded anyway. A broken board is reported on
+    /// the console and the display, the current one keeps running. Sounds are only queued for
+    /// loading, `finish_loads` swaps them in one by one and the action they replace stays until
+    /// then.
+    fn read_config_impl(&mut self, changed_assets: &HashSet<PathBuf>) -> Result<(), MyError> {
+        let board_path = self.board_path.clone();
+
+        let board = match validation::load_board(&board_path) {
+            Ok(value) => value,
+            Err(problems) => {
+                self.report_config_errors(&board_path, problems);
+                return Ok(());
+            }
+        };
+
+        let mut new_configs = HashMap::new();
+        let mut loaded_actions = HashMap::new();
+        let mut sound_loads = vec![];
+        let mut problems = vec![];
+
+        let mut page_names = vec![];
+
+        for page in board.pages {
+            for config in page.actions {
+                let slot = ActionSlot {
+                    page: page.name.clone(),
+                    button: config.button(),
+                };
+
+                // A sound that failed to load before is tried again
+                let unchanged = self.action_configs.get(&slot) == Some(&config)
+                    && (self.button_actions.contains_key(&slot)
+                        || self.loading.contains_key(&slot))
+                    && !self.outdated.contains(&slot)
+                    && !config
+                        .asset_paths()
+                        .iter()
+                        .any(|path| changed_assets.contains(&canonical_path(path)));
+
+                if !unchanged {
+                    match SoundMode::load_action(config.clone()) {
+                        Ok(ActionLoad::Ready(action)) => {
+                            loaded_actions.insert(slot.clone(), action);
+                        }
+                        Ok(ActionLoad::Sound(load)) => sound_loads.push((slot.clone(), load)),
+                        Err(problem) => problems.push(problem),
+                    }
+                }
+
+                new_configs.insert(slot, config);
+            }
+
+            page_names.push(page.name);
+        }
+
+        if !problems.is_empty() {
+            self.report_config_errors(&board_path, problems);
+            return Ok(());
+        }
+
+        let reloaded: HashSet<ActionSlot> = loaded_actions
+            .keys()
+            .chain(sound_loads.iter().map(|(slot, _)| slot))
+            .cloned()
+            .collect();
+
+        // stop and drop whatever was removed or replaced, an action waiting for its new sound
+        // keeps playing until the sound loaded
+        let mut removed = 0;
+        self.button_actions.retain(|slot, action| {
+            let keep = new_configs.contains_key(slot) && !loaded_actions.contains_key(slot);
+
+            if !keep {
+                if let Action::Sound(sound) = action {
+                    sound.stop();
+                }
+                removed += 1;
             }
+
+            keep
+        });
+
+        if !self.action_configs.is_empty() {
+            let kept = self
+                .button_actions
+                .keys()
+                .filter(|slot| !reloaded.contains(*slot))
+                .count();
+
+            println!(
+                "Reloaded board: {} actions loaded, {} kept, {} replaced or removed.",
+                reloaded.len(),
+                kept,
+                removed
+            );
         }
 
-        /*
-        // Add the same path to the file ID cache. The cache uses unique file IDs
-        // provided by the file system and is used to stich together rename events
-        // in case the notification back-end doesn't emit rename cookies.
-        debouncer.
-            .add_root(Path::new("."), RecursiveMode::Recursive);
-*/
+        // Loads of removed or replaced sounds are outdated, their results get dropped
+        self.loading
+            .retain(|slot, _| new_configs.contains_key(slot) && !reloaded.contains(slot));
+        self.load_errors.clear();
+        self.outdated
+            .retain(|slot| new_configs.contains_key(slot) && !loaded_actions.contains_key(slot));
 
-        self.read_config_impl(Path::new(path))?;
+        if self.loading.is_empty() {
+            self.loads_queued = 0;
+            self.loads_done = 0;
+        }
 
-        self.file_watcher = Some(rx);
-        self.file_watcher_intern = Some(debouncer);
+        for (slot, load) in sound_loads {
+            let ticket = self.loader.load(slot.clone(), load)?;
+            self.loading.insert(slot, ticket);
+            self.loads_queued += 1;
+        }
 
-        Ok(())
+        {
+            let sound_system = &self.sound_system;
+            let mut sound_system = lock_or_return_err!(sound_system);
+            sound_system.set_buses(&board.buses);
+            sound_system.set_ducking(&board.ducking);
+        }
+
+        self.level_lights = board.level_lights;
+
+        self.button_actions.extend(loaded_actions);
+        self.action_configs = new_configs;
+
+        // Stay on the page shown before if it still exists
+        let current_name = self.pages.get(self.current_page).cloned();
+        self.current_page = page_names
+            .iter()
+            .position(|name| Some(name) == current_name.as_ref())
+            .unwrap_or(0);
+        self.pages = page_names;
+        self.config_errors.clear();
+
+        self.update_watches()
     }
 
-    fn read_config_impl(&mut self, path: &Path) -> Result<(), MyError> {
-        let mut file = match File::open(path) {
-            Ok(val) => val,
-            Err(_) => return Err(MyError::ConfigFileNotFound("Sound Mode config")),
-        };
-        let mut config_string = String::new();
-        file.read_to_string(&mut config_string)
-            .expect("Could not read config file.");
+    /// Takes in the sounds that finished loading, returns whether any pad changed.
+    fn finish_loads(&mut self) -> bool {
+        let mut changed = false;
 
-        let action_configs: ActionConfigs =
-            ron::de::from_str(&config_string).expect("Could not deserialize SoundConfig.");
+        while let Some(loaded) = self.loader.try_finished() {
+            // Outdated by a reload meanwhile
+            if self.loading.get(&loaded.slot) != Some(&loaded.ticket) {
+                continue;
+            }
 
-        for action in action_configs.actions {
-            match action {
-                ActionConfig::SoundConfig {
-                    button,
-                    path,
-                    looping,
+            self.loading.remove(&loaded.slot);
+            self.loads_done += 1;
+            changed = true;
+
+            match loaded.sound {
+                Ok(sound) => {
+                    self.outdated.remove(&loaded.slot);
+
+                    let replaced = self
+                        .button_actions
+                        .insert(loaded.slot, Action::Sound(Box::new(sound)));
+
+                    if let Some(Action::Sound(mut sound)) = replaced {
+                        sound.stop();
+                    }
+                }
+                Err(problem) => {
+                    println!("{}", problem);
+                    self.load_errors.push(problem);
+
+                    if self.button_actions.contains_key(&loaded.slot) {
+                        self.outdated.insert(loaded.slot);
+                    }
+                }
+            }
+        }
+
+        if changed && self.loading.is_empty() {
+            println!("Loaded {} sounds.", self.loads_done);
+        }
+
+        changed
+    }
+
+    fn load_action(config: ActionConfig) -> Result<ActionLoad, String> {
+        match config {
+            ActionConfig::SoundConfig {
+                path,
+                selection,
+                looping,
+                fade_in,
+                fade_out,
+                fade_in_ms,
+                fade_out_ms,
+                fade_curve,
+                gain,
+                group,
+                bus,
+                pitch_range,
+                gain_range,
+                seed,
+                streaming,
+                predecode,
+                ..
+            } => {
+                let name = sound_name(Path::new(path.main_path()));
+
+                let settings = SoundSettings {
+                    looped: looping,
                     fade_in,
                     fade_out,
+                    fade_in_duration: Duration::from_millis(fade_in_ms),
+                    fade_out_duration: Duration::from_millis(fade_out_ms),
+                    fade_curve,
                     gain,
-                } => self.add_action(
-                    button,
-                    Action::Sound({
-                        match Sound::load(path, looping, fade_in, fade_out, gain) {
-                            Ok(val) => val,
-                            Err(_) => {
-                                return Err(MyError::SoundSystemError("Could not load sound."))
-                            }
+                    group,
+                    bus,
+                    selection,
+                    pitch_range,
+                    gain_range,
+                    seed,
+                    streaming,
+                    predecode,
+                };
+
+                match path.files() {
+                    Ok(files) => Ok(ActionLoad::Sound(SoundLoad {
+                        name,
+                        files,
+                        settings,
+                        config_path: path.main_path().to_string(),
+                    })),
+                    Err(err) => Err(format!("Could not load \"{}\": {}", path.main_path(), err)),
+                }
+            }
+            ActionConfig::CommandConfig {
+                command, mut args, ..
+            } => {
+                for arg in &mut args {
+                    *arg = arg.trim().to_string();
+                }
+                Ok(ActionLoad::Ready(Action::Command(Command::new(
+                    command, args,
+                ))))
+            }
+            ActionConfig::SceneConfig { sounds, .. } => {
+                Ok(ActionLoad::Ready(Action::Scene(Scene::new(sounds))))
+            }
+        }
+    }
+
+    /// Fades out every other playing sound of the groups of the sounds in `started`.
+    ///
+    /// Whatever started the sounds, a press of their pad or a scene.
+    fn choke_groups(&mut self, started: &HashSet<ActionSlot>) {
+        let groups: HashSet<String> = started
+            .iter()
+            .filter_map(|slot| match self.button_actions.get(slot) {
+                Some(Action::Sound(sound)) => sound.group.clone(),
+                _ => None,
+            })
+            .collect();
+
+        if groups.is_empty() {
+            return;
+        }
+
+        for (slot, action) in &mut self.button_actions {
+            if let Action::Sound(sound) = action
+                && !started.contains(slot)
+                && sound
+                    .group
+                    .as_ref()
+                    .is_some_and(|group| groups.contains(group))
+                && sound.is_playing()
+            {
+                sound.start_fade_out();
+            }
+        }
+    }
+
+    /// Fades the sounds of a scene to their gains and fades out every other loop.
+    ///
+    /// Sounds on other pages count as well, a scene is about what is heard.
+    fn crossfade_to_scene(
+        &mut self,
+        scene_slot: &ActionSlot,
+        targets: &HashMap<String, f32>,
+    ) -> Result<(), MyError> {
+        let mut started = HashSet::new();
+
+        for (slot, action) in &mut self.button_actions {
+            match action {
+                Action::Sound(sound) => {
+                    if let Some(gain) = targets.get(&sound.get_name()) {
+                        sound.fade_to(*gain, &self.sound_system)?;
+
+                        if *gain > 0.0 {
+                            started.insert(slot.clone());
                         }
-                    }),
-                ),
-                ActionConfig::CommandConfig {
-                    button,
-                    command,
-                    mut args,
-                } => {
-                    for arg in &mut args {
-                        *arg = arg.trim().to_string();
+                    } else if sound.looped && sound.is_playing() {
+                        sound.start_fade_out();
                     }
-                    self.add_action(button, Action::Command(Command::new(command, args)))
                 }
+                Action::Scene(scene) => scene.set_active(slot == scene_slot),
+                Action::Command(_) => {}
+            }
+        }
+
+        self.choke_groups(&started);
+
+        Ok(())
+    }
+
+    /// Watches the directories of the board and its audio files.
+    ///
+    /// Directories instead of the files themselves, as editors often save by replacing the file
+    /// which would end a watch on it. A directory of variations is watched itself, so files added
+    /// to it are picked up.
+    fn update_watches(&mut self) -> Result<(), MyError> {
+        let Some(debouncer) = &mut self.file_watcher_intern else {
+            return Ok(());
+        };
+
+        let mut needed_dirs = HashSet::new();
+
+        let files = self
+            .action_configs
+            .values()
+            .flat_map(|config| config.asset_paths())
+            .map(|path| canonical_path(&path))
+            .chain([self.board_path.clone()]);
+
+        for file in files {
+            if file.is_dir() {
+                needed_dirs.insert(file);
+            } else if let Some(dir) = file.parent() {
+                needed_dirs.insert(dir.to_path_buf());
             }
         }
 
+        for dir in self.watched_dirs.difference(&needed_dirs) {
+            // Fails if the directory is gone, which ends the watch as well
+            let _ = debouncer.unwatch(dir);
+        }
+
+        for dir in needed_dirs.difference(&self.watched_dirs) {
+            if debouncer.watch(dir, RecursiveMode::NonRecursive).is_err() {
+                return Err(MyError::FileWatcher(
+                    "Could not add watch to debounced watcher.",
+                ));
+            }
+        }
+
+        self.watched_dirs = needed_dirs;
+
         Ok(())
     }
+
+    /// Splits changed files into whether the board changed and the changed audio files.
+    ///
+    /// An audio file added to or removed from a directory of variations changes the directory.
+    fn changed_files(&self, paths: HashSet<PathBuf>) -> (bool, HashSet<PathBuf>) {
+        let paths: HashSet<PathBuf> = paths.iter().map(|path| canonical_path(path)).collect();
+        let board_changed = paths.contains(&self.board_path);
+
+        let assets: HashSet<PathBuf> = self
+            .action_configs
+            .values()
+            .flat_map(|config| config.asset_paths())
+            .map(|path| canonical_path(&path))
+            .collect();
+
+        let directories = paths
+            .iter()
+            .filter(|path| is_audio_file(path))
+            .filter_map(|path| path.parent().map(Path::to_path_buf));
+
+        let changed_assets = paths
+            .iter()
+            .cloned()
+            .chain(directories)
+            .filter(|path| assets.contains(path))
+            .collect();
+
+        (board_changed, changed_assets)
+    }
+
+    /// Slots of the playing sounds above `LEVEL_LIGHT_THRESHOLD`.
+    fn loud_slots(&self) -> HashSet<ActionSlot> {
+        self.button_actions
+            .iter()
+            .filter(|(_, action)| match action {
+                Action::Sound(sound) => {
+                    sound.is_playing() && sound.meter().rms() > LEVEL_LIGHT_THRESHOLD
+                }
+                Action::Command(_) | Action::Scene(_) => false,
+            })
+            .map(|(slot, _)| slot.clone())
+            .collect()
+    }
+
+    /// The slot of a button on the page currently shown.
+    fn current_slot(&self, button: ButtonType) -> ActionSlot {
+        ActionSlot {
+            page: self
+                .pages
+                .get(self.current_page)
+                .cloned()
+                .unwrap_or_default(),
+            button,
+        }
+    }
+
+    fn change_page(&mut self, change: isize) -> LightAction {
+        let page = self
+            .current_page
+            .saturating_add_signed(change)
+            .min(self.pages.len().saturating_sub(1));
+
+        if page == self.current_page {
+            return LightAction::None;
+        }
+
+        self.current_page = page;
+        println!("Showing page \"{}\"", self.pages[page]);
+
+        LightAction::ClearAndReapply
+    }
+
+    fn report_config_errors(&mut self, path: &Path, problems: Vec<String>) {
+        println!("Could not load the board {}:", path.display());
+        for problem in &problems {
+            println!("  {}", problem);
+        }
+
+        self.config_errors = problems;
+    }
+}
+
+/// The bus an encoder above the display controls, from the left.
+fn bus_encoder_index(encoder_name: EncoderName) -> Option<usize> {
+    match encoder_name {
+        EncoderName::Control71 => Some(0),
+        EncoderName::Control72 => Some(1),
+        EncoderName::Control73 => Some(2),
+        EncoderName::Control74 => Some(3),
+        EncoderName::Control75 => Some(4),
+        EncoderName::Control76 => Some(5),
+        EncoderName::Control77 => Some(6),
+        _ => None,
+    }
+}
+
+/// Where the bars of that many buses start, they fill the space towards the master volume.
+fn bus_area_left(bus_count: usize) -> i32 {
+    BUS_AREA_RIGHT - bus_count as i32 * BUS_COLUMN_WIDTH
+}
+
+/// As much of a FONT_10X20 text starting at `x` as fits left of `right`.
+fn clip_text(text: &str, x: i32, right: i32) -> String {
+    let max_length = ((right - x) / CHAR_WIDTH).max(0) as usize;
+
+    text.chars().take(max_length).collect()
+}
+
+/// Audio paths in the board are relative to the working directory and may go through symlinks.
+///
+/// A path that is gone, like a removed file, is resolved through its directory.
+fn canonical_path(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(dir), Some(name)) => match dir.canonicalize() {
+            Ok(dir) => dir.join(name),
+            Err(_) => absolute,
+        },
+        _ => absolute,
+    }
 }
 
 impl SoundMode {
-    pub fn playing_sound_names(&self) -> Vec<(String, bool)> {
+    /// Names of the playing sounds, whether they loop and their level.
+    pub fn playing_sound_names(&self) -> Vec<(String, bool, &LevelMeter)> {
         let mut names = vec![];
 
-        for (_button, action) in &self.button_actions {
+        for action in self.button_actions.values() {
             match action {
                 Action::Sound(sound) => {
                     if sound.is_playing() {
-                        names.push((sound.get_name(), sound.looped));
+                        let name = match sound.variation_name() {
+                            Some(variation) => format!("{} ({})", sound.get_name(), variation),
+                            None => sound.get_name(),
+                        };
+
+                        names.push((name, sound.looped, sound.meter()));
                     }
                 }
-                Action::Command(_) => {}
+                Action::Command(_) | Action::Scene(_) => {}
             }
         }
 
         names
     }
 
-    fn draw_volume(
+    pub fn draw_volume<D>(
         sound_system: &Arc<Mutex<SoundSystem>>,
-        display: &mut Push2Display,
-    ) -> Result<(), MyError> {
+        display: &mut D,
+    ) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
         const VOLUME_BAR_X: i32 = 880;
         const VOLUME_BAR_Y: i32 = 10;
 
@@ -264,7 +754,7 @@ impl SoundMode {
         Rectangle::new(
             Point {
                 x: VOLUME_BAR_X - 5,
-                y: VOLUME_BAR_Y as i32,
+                y: VOLUME_BAR_Y,
             },
             Size {
                 width: 5,
@@ -279,9 +769,7 @@ impl SoundMode {
             "400%",
             Point {
                 x: VOLUME_BAR_X - 50,
-                y: ((VOLUME_BAR_Y as f32)
-                    + (1.0 - 4.0 / 4.0) * (VOLUME_BAR_HEIGHT as f32)
-                    + TEXT_SIZE_OFFSET) as i32,
+                y: ((VOLUME_BAR_Y as f32) + TEXT_SIZE_OFFSET) as i32,
             },
             MonoTextStyle::new(&FONT_10X20, Bgr565::WHITE),
         )
@@ -314,10 +802,255 @@ impl SoundMode {
         )
         .draw(display)?;
 
+        SoundMode::draw_master_meter(sound_system, display)?;
+        SoundMode::draw_gain_reduction(sound_system, display)?;
+        SoundMode::draw_bus_volumes(sound_system, display)?;
+
+        Ok(())
+    }
+
+    /// Level of the master output right of the volume bar, rising from the bottom.
+    fn draw_master_meter<D>(
+        sound_system: &Arc<Mutex<SoundSystem>>,
+        display: &mut D,
+    ) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
+        const METER_X: i32 = 918;
+        const METER_Y: i32 = 10;
+
+        const METER_HEIGHT: u32 = 140;
+        const METER_WIDTH: u32 = 10;
+
+        let meter = sound_system
+            .try_lock()
+            .expect("Couldn't lock SoundSystem.")
+            .master_meter();
+
+        // Outline
+        Rectangle::new(
+            Point {
+                x: METER_X,
+                y: METER_Y,
+            },
+            Size {
+                width: METER_WIDTH,
+                height: METER_HEIGHT,
+            },
+        )
+        .into_styled(PrimitiveStyle::with_stroke(Bgr565::WHITE, 1))
+        .draw(display)?;
+
+        let rms = meter_fill(meter.rms());
+
+        Rectangle::new(
+            Point {
+                x: METER_X,
+                y: ((METER_Y as f32) + (1.0 - rms) * (METER_HEIGHT as f32)) as i32,
+            },
+            Size {
+                width: METER_WIDTH,
+                height: ((METER_HEIGHT as f32) * rms) as u32,
+            },
+        )
+        .into_styled(PrimitiveStyle::with_fill(Bgr565::GREEN))
+        .draw(display)?;
+
+        let peak = meter_fill(meter.peak());
+
+        SoundMode::draw_peak_mark(
+            display,
+            Point {
+                x: METER_X,
+                y: ((METER_Y as f32) + (1.0 - peak) * (METER_HEIGHT as f32)) as i32,
+            },
+            Size {
+                width: METER_WIDTH,
+                height: 2,
+            },
+            meter.peak(),
+        )?;
+
         Ok(())
     }
 
-    fn display_sounds(&self, display: &mut Push2Display) -> Result<(), MyError> {
+    /// How much the master limiter turns the output down, hanging from the top right of the meter.
+    fn draw_gain_reduction<D>(
+        sound_system: &Arc<Mutex<SoundSystem>>,
+        display: &mut D,
+    ) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
+        const REDUCTION_X: i32 = 934;
+        const REDUCTION_Y: i32 = 10;
+
+        const REDUCTION_HEIGHT: u32 = 140;
+        const REDUCTION_WIDTH: u32 = 10;
+
+        // Reduction that fills the whole bar
+        const REDUCTION_RANGE_DB: f32 = 12.0;
+
+        let reduction = sound_system
+            .try_lock()
+            .expect("Couldn't lock SoundSystem.")
+            .limiter_reduction()
+            .decibel();
+
+        // Outline
+        Rectangle::new(
+            Point {
+                x: REDUCTION_X,
+                y: REDUCTION_Y,
+            },
+            Size {
+                width: REDUCTION_WIDTH,
+                height: REDUCTION_HEIGHT,
+            },
+        )
+        .into_styled(PrimitiveStyle::with_stroke(Bgr565::WHITE, 1))
+        .draw(display)?;
+
+        let fill = (reduction / REDUCTION_RANGE_DB).clamp(0.0, 1.0);
+
+        Rectangle::new(
+            Point {
+                x: REDUCTION_X,
+                y: REDUCTION_Y,
+            },
+            Size {
+                width: REDUCTION_WIDTH,
+                height: ((REDUCTION_HEIGHT as f32) * fill) as u32,
+            },
+        )
+        .into_styled(PrimitiveStyle::with_fill(Bgr565::RED))
+        .draw(display)?;
+
+        Text::new(
+            "GR",
+            Point {
+                x: REDUCTION_X - 1,
+                y: REDUCTION_Y + REDUCTION_HEIGHT as i32 + 8,
+            },
+            MonoTextStyle::new(&FONT_6X10, Bgr565::WHITE),
+        )
+        .draw(display)?;
+
+        Ok(())
+    }
+
+    /// Text has to end here to stay clear of the bus volumes.
+    fn text_area_right(&self) -> i32 {
+        let bus_count = self
+            .sound_system
+            .try_lock()
+            .expect("Couldn't lock SoundSystem.")
+            .buses()
+            .len();
+
+        bus_area_left(bus_count)
+    }
+
+    /// A smaller bar per bus left of the master volume, in the order of their encoders.
+    fn draw_bus_volumes<D>(
+        sound_system: &Arc<Mutex<SoundSystem>>,
+        display: &mut D,
+    ) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
+        const BUS_BAR_Y: i32 = 30;
+        const BUS_BAR_HEIGHT: u32 = 110;
+        const BUS_BAR_WIDTH: u32 = 14;
+
+        const BUS_NAME_Y: i32 = 155;
+        const MAX_BUS_NAME_LENGTH: usize = 5;
+
+        let buses: Vec<(String, f32)> = sound_system
+            .try_lock()
+            .expect("Couldn't lock SoundSystem.")
+            .buses()
+            .iter()
+            .map(|bus| {
+                (
+                    bus.name().to_string(),
+                    bus.volume_factor() / (MAX_VOLUME as f32 / DEFAULT_VOLUME as f32),
+                )
+            })
+            .collect();
+
+        let first_x = bus_area_left(buses.len());
+
+        for (index, (name, volume_factor)) in buses.iter().enumerate() {
+            let x = first_x + index as i32 * BUS_COLUMN_WIDTH;
+
+            // Outline
+            Rectangle::new(
+                Point { x, y: BUS_BAR_Y },
+                Size {
+                    width: BUS_BAR_WIDTH,
+                    height: BUS_BAR_HEIGHT,
+                },
+            )
+            .into_styled(PrimitiveStyle::with_stroke(Bgr565::WHITE, 1))
+            .draw(display)?;
+
+            // Fill for current volume
+            Rectangle::new(
+                Point {
+                    x,
+                    y: ((BUS_BAR_Y as f32) + (1.0 - volume_factor) * (BUS_BAR_HEIGHT as f32))
+                        as i32,
+                },
+                Size {
+                    width: BUS_BAR_WIDTH,
+                    height: ((BUS_BAR_HEIGHT as f32) * volume_factor) as u32,
+                },
+            )
+            .into_styled(PrimitiveStyle::with_fill(Bgr565::WHITE))
+            .draw(display)?;
+
+            // 1.0 marker
+            Rectangle::new(
+                Point {
+                    x: x + BUS_BAR_WIDTH as i32,
+                    y: ((BUS_BAR_Y as f32) + (1.0 - 1.0 / 4.0) * (BUS_BAR_HEIGHT as f32)) as i32,
+                },
+                Size {
+                    width: 4,
+                    height: 2,
+                },
+            )
+            .into_styled(PrimitiveStyle::with_fill(Bgr565::WHITE))
+            .draw(display)?;
+
+            Text::new(
+                &name.chars().take(MAX_BUS_NAME_LENGTH).collect::<String>(),
+                Point { x, y: BUS_NAME_Y },
+                MonoTextStyle::new(&FONT_6X10, Bgr565::WHITE),
+            )
+            .draw(display)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn display_sounds<D>(&self, display: &mut D) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
+        if !self.config_errors.is_empty() {
+            self.display_config_errors(display)?;
+            SoundMode::draw_volume(&self.sound_system, display)?;
+
+            return Ok(());
+        }
+
         // One-shots header
         Text::new(
             "One-Shots",
@@ -354,21 +1087,55 @@ impl SoundMode {
         .into_styled(PrimitiveStyle::with_fill(Bgr565::WHITE))
         .draw(display)?;
 
+        // Page of the pads, only worth showing when there is more than one
+        if self.pages.len() > 1 {
+            // Fits between the loops and the volume bar labels
+            const MAX_PAGE_TEXT_LENGTH: usize = 22;
+
+            let page_text = format!(
+                "{}/{} {}",
+                self.current_page + 1,
+                self.pages.len(),
+                self.pages[self.current_page]
+            );
+
+            Text::new(
+                &page_text
+                    .chars()
+                    .take(MAX_PAGE_TEXT_LENGTH)
+                    .collect::<String>(),
+                Point { x: 600, y: 15 },
+                MonoTextStyle::new(&FONT_10X20, Bgr565::YELLOW),
+            )
+            .draw(display)?;
+        }
+
         // Running sounds
+        let text_right = self.text_area_right();
         let mut num_oneshots = 0;
         let mut num_looped = 0;
 
-        for (name, looping) in self.playing_sound_names().iter() {
+        for (name, looping, meter) in self.playing_sound_names().iter() {
+            let x = if *looping { 400 } else { 50 };
+            let y = if *looping { num_looped } else { num_oneshots } * 15 + 40;
+
             Text::new(
-                name,
-                Point {
-                    x: if *looping { 400 } else { 50 },
-                    y: if *looping { num_looped } else { num_oneshots } * 15 + 40,
-                },
+                &clip_text(name, x, text_right),
+                Point { x, y },
                 MonoTextStyle::new(&FONT_10X20, Bgr565::WHITE),
             )
             .draw(display)?;
 
+            // Left of the name, in the margin of its column
+            SoundMode::draw_level_meter(
+                display,
+                Point {
+                    x: x - 45,
+                    y: y - 10,
+                },
+                meter,
+            )?;
+
             if *looping {
                 num_looped += 1;
             } else {
@@ -376,33 +1143,241 @@ impl SoundMode {
             }
         }
 
+        self.display_loading(display)?;
+
         // Volume bar
 
         SoundMode::draw_volume(&self.sound_system, display)?;
 
         Ok(())
     }
+
+    /// A small horizontal meter, the average level filled and a mark at the peak.
+    fn draw_level_meter<D>(
+        display: &mut D,
+        position: Point,
+        meter: &LevelMeter,
+    ) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
+        const METER_WIDTH: u32 = 40;
+        const METER_HEIGHT: u32 = 8;
+
+        Rectangle::new(
+            position,
+            Size {
+                width: METER_WIDTH,
+                height: METER_HEIGHT,
+            },
+        )
+        .into_styled(PrimitiveStyle::with_stroke(Bgr565::WHITE, 1))
+        .draw(display)?;
+
+        Rectangle::new(
+            position,
+            Size {
+                width: (METER_WIDTH as f32 * meter_fill(meter.rms())) as u32,
+                height: METER_HEIGHT,
+            },
+        )
+        .into_styled(PrimitiveStyle::with_fill(Bgr565::GREEN))
+        .draw(display)?;
+
+        SoundMode::draw_peak_mark(
+            display,
+            Point {
+                x: position.x + (METER_WIDTH as f32 * meter_fill(meter.peak())) as i32 - 2,
+                y: position.y,
+            },
+            Size {
+                width: 2,
+                height: METER_HEIGHT,
+            },
+            meter.peak(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Yellow, or red once the peak reaches full scale and would clip.
+    fn draw_peak_mark<D>(
+        display: &mut D,
+        position: Point,
+        size: Size,
+        peak: f32,
+    ) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
+        if peak <= 0.0 {
+            return Ok(());
+        }
+
+        let color = if peak >= 1.0 {
+            Bgr565::RED
+        } else {
+            Bgr565::YELLOW
+        };
+
+        Rectangle::new(position, size)
+            .into_styled(PrimitiveStyle::with_fill(color))
+            .draw(display)?;
+
+        Ok(())
+    }
+
+    /// Progress of the sounds still loading and the ones that failed, along the bottom.
+    fn display_loading<D>(&self, display: &mut D) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
+        const PROGRESS_BAR_X: i32 = 250;
+        const PROGRESS_BAR_Y: i32 = 140;
+
+        const PROGRESS_BAR_HEIGHT: u32 = 12;
+        // Leaves room for the bus volumes on the right
+        const PROGRESS_BAR_WIDTH: u32 = 300;
+
+        if !self.loading.is_empty() {
+            Text::new(
+                &format!("Loading {}/{}", self.loads_done, self.loads_queued),
+                Point { x: 50, y: 152 },
+                MonoTextStyle::new(&FONT_10X20, Bgr565::YELLOW),
+            )
+            .draw(display)?;
+
+            // Outline
+            Rectangle::new(
+                Point {
+                    x: PROGRESS_BAR_X,
+                    y: PROGRESS_BAR_Y,
+                },
+                Size {
+                    width: PROGRESS_BAR_WIDTH,
+                    height: PROGRESS_BAR_HEIGHT,
+                },
+            )
+            .into_styled(PrimitiveStyle::with_stroke(Bgr565::YELLOW, 2))
+            .draw(display)?;
+
+            let progress = self.loads_done as f32 / self.loads_queued.max(1) as f32;
+
+            // Fill for the loaded sounds
+            Rectangle::new(
+                Point {
+                    x: PROGRESS_BAR_X,
+                    y: PROGRESS_BAR_Y,
+                },
+                Size {
+                    width: (PROGRESS_BAR_WIDTH as f32 * progress) as u32,
+                    height: PROGRESS_BAR_HEIGHT,
+                },
+            )
+            .into_styled(PrimitiveStyle::with_fill(Bgr565::YELLOW))
+            .draw(display)?;
+        } else if !self.load_errors.is_empty() {
+            Text::new(
+                &format!(
+                    "{} sounds could not be loaded, see console",
+                    self.load_errors.len()
+                ),
+                Point { x: 50, y: 152 },
+                MonoTextStyle::new(&FONT_10X20, Bgr565::RED),
+            )
+            .draw(display)?;
+        }
+
+        Ok(())
+    }
+
+    fn display_config_errors<D>(&self, display: &mut D) -> Result<(), MyError>
+    where
+        D: DrawTarget<Color = Bgr565>,
+        MyError: From<D::Error>,
+    {
+        const MAX_LINES: usize = 5;
+
+        // Everything left of the bus volumes
+        let text_right = self.text_area_right();
+
+        Text::new(
+            "Board not loaded, still using the previous one:",
+            Point { x: 10, y: 15 },
+            MonoTextStyle::new(&FONT_10X20, Bgr565::RED),
+        )
+        .draw(display)?;
+
+        for (index, problem) in self.config_errors.iter().take(MAX_LINES).enumerate() {
+            Text::new(
+                &clip_text(problem, 10, text_right),
+                Point {
+                    x: 10,
+                    y: index as i32 * 20 + 40,
+                },
+                MonoTextStyle::new(&FONT_10X20, Bgr565::WHITE),
+            )
+            .draw(display)?;
+        }
+
+        if self.config_errors.len() > MAX_LINES {
+            Text::new(
+                &format!(
+                    "... {} more, see console",
+                    self.config_errors.len() - MAX_LINES
+                ),
+                Point {
+                    x: 10,
+                    y: MAX_LINES as i32 * 20 + 40,
+                },
+                MonoTextStyle::new(&FONT_10X20, Bgr565::WHITE),
+            )
+            .draw(display)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl super::DeviceMode for SoundMode {
     fn button_press(&mut self, note_name: NoteName) -> Result<LightAction, MyError> {
-        if self
-            .button_actions
-            .contains_key(&ButtonType::Note(note_name))
-        {
+        let slot = self.current_slot(ButtonType::Note(note_name));
+
+        if self.button_actions.contains_key(&slot) {
             let playing = self
                 .button_actions
-                .get_mut(&ButtonType::Note(note_name))
+                .get_mut(&slot)
                 .expect("Could not get hash-map entry despite previous check.")
                 .execute(&mut self.sound_system)?;
 
             if playing == ActionState::FadingOut || playing == ActionState::Stopped {
                 println!("Stopping a sound.");
             }
+
+            match &self.button_actions[&slot] {
+                Action::Scene(scene) => {
+                    let targets = scene.targets().clone();
+                    self.crossfade_to_scene(&slot, &targets)?;
+                }
+                Action::Sound(_) => {
+                    let started = playing == ActionState::Started
+                        || playing == ActionState::FadingIn
+                        || playing == ActionState::Playing;
+
+                    if started {
+                        self.choke_groups(&HashSet::from([slot.clone()]));
+                    }
+                }
+                Action::Command(_) => {}
+            }
+
             return Ok(LightAction::Reapply);
         }
 
-        return Ok(LightAction::None);
+        Ok(LightAction::None)
     }
 
     fn control_press(&mut self, control_name: ControlName) -> Result<LightAction, MyError> {
@@ -430,26 +1405,21 @@ impl super::DeviceMode for SoundMode {
                     .expect("Couldn't lock SoundSystem")
                     .repress_mode = internal_state;
 
-                return Ok(LightAction::Reapply);
-            }
-            ControlName::Control20 => {
-                return Ok(LightAction::Reapply);
-            }
-            ControlName::Control21 => {
-                return Ok(LightAction::Reapply);
-            }
-            ControlName::Control24 => {
-                return Ok(LightAction::None);
-            }
-            ControlName::Control25 => {
-                return Ok(LightAction::None);
+                Ok(LightAction::Reapply)
             }
+            ControlName::Control20 => Ok(LightAction::Reapply),
+            ControlName::Control21 => Ok(LightAction::Reapply),
+            ControlName::Control22 => Ok(LightAction::Reapply),
+            ControlName::Control24 => Ok(LightAction::None),
+            ControlName::Control25 => Ok(LightAction::None),
+            ControlName::Control44 | ControlName::Control62 => Ok(self.change_page(-1)),
+            ControlName::Control45 | ControlName::Control63 => Ok(self.change_page(1)),
         }
     }
 
     fn apply_button_lights(
         &mut self,
-        midiconn: &Arc<Mutex<crate::midi::MidiConnection>>,
+        midiconn: &crate::midi::SharedMidi,
         button_values: &HashMap<u8, ButtonType>,
     ) -> Result<(), MyError> {
         let mut mutex_guard = midiconn.try_lock().expect("Couldn't lock MidiConnection");
@@ -477,34 +1447,57 @@ impl super::DeviceMode for SoundMode {
                     ControlName::Control21 => {
                         mutex_guard.send_to_device(&[0b10110000, *address, 123u8])?;
                     }
+                    ControlName::Control22 => {
+                        mutex_guard.send_to_device(&[0b10110000, *address, 123u8])?;
+                    }
                     ControlName::Control24 => {
                         mutex_guard.send_to_device(&[0b10110000, *address, 0u8])?;
                     }
                     ControlName::Control25 => {
                         mutex_guard.send_to_device(&[0b10110000, *address, 0u8])?;
                     }
+                    ControlName::Control44 | ControlName::Control62 => {
+                        // Lit while there is a page to go back to
+                        let color = if self.current_page > 0 { 122u8 } else { 0u8 };
+                        mutex_guard.send_to_device(&[0b10110000, *address, color])?;
+                    }
+                    ControlName::Control45 | ControlName::Control63 => {
+                        let color = if self.current_page + 1 < self.pages.len() {
+                            122u8
+                        } else {
+                            0u8
+                        };
+                        mutex_guard.send_to_device(&[0b10110000, *address, color])?;
+                    }
                 },
                 ButtonType::Note(_note_name) => {
-                    if self.button_actions.contains_key(name) {
-                        match self.button_actions[name].is_running() {
-                            ActionState::None | ActionState::Stopped => {
+                    let slot = self.current_slot(*name);
+
+                    if self.button_actions.contains_key(&slot) {
+                        match self.button_actions[&slot].is_running() {
+                            // A sound on its way out, e.g. after its group handed over, already
+                            // looks idle
+                            ActionState::None | ActionState::Stopped | ActionState::FadingOut => {
                                 mutex_guard.send_to_device(&[
                                     0b10010000,
                                     *address,
-                                    self.button_actions[name].get_default_color(),
+                                    self.button_actions[&slot].get_default_color(),
                                 ])?;
                             }
-                            ActionState::Playing
-                            | ActionState::Started
-                            | ActionState::FadingIn
-                            | ActionState::FadingOut => {
-                                mutex_guard.send_to_device(&[
-                                    0b10010000,
-                                    *address,
-                                    self.button_actions[name].get_active_color(),
-                                ])?;
+                            ActionState::Playing | ActionState::Started | ActionState::FadingIn => {
+                                // Quiet moments of the sound dim its pad to the idle colour
+                                let color = if self.level_lights && !self.loud_slots.contains(&slot)
+                                {
+                                    self.button_actions[&slot].get_default_color()
+                                } else {
+                                    self.button_actions[&slot].get_active_color()
+                                };
+
+                                mutex_guard.send_to_device(&[0b10010000, *address, color])?;
                             }
                         }
+                    } else if self.loading.contains_key(&slot) {
+                        mutex_guard.send_to_device(&[0b10010000, *address, LOADING_COLOR])?;
                     } else {
                         mutex_guard.send_to_device(&[0b10010000, *address, 0_u8])?;
                     }
@@ -520,40 +1513,47 @@ impl super::DeviceMode for SoundMode {
         Ok(())
     }
 
+    fn background_update(&mut self) -> Result<(), MyError> {
+        // Fades, ducking and reloads go on while another mode is shown, the lights are applied
+        // again when switching back
+        self.update()?;
+
+        Ok(())
+    }
+
     fn update(&mut self) -> Result<LightAction, MyError> {
-        let mut need_reload = None;
+        let mut changed = HashSet::new();
 
         let mut need_ligh_refresh = LightAction::None;
 
         if let Some(ref watcher) = self.file_watcher {
             while let Ok(msg) = watcher.try_recv() {
-                for path in msg.event.paths {
-                    need_reload = Some(path.clone());
+                // Reading the files ourselves shows up as access
+                if msg.event.kind.is_access() {
+                    continue;
                 }
-            }
-        }
 
-        if let Some(changed) = need_reload {
-            // stop all sounds
-            for (_btn_name, action) in &mut self.button_actions {
-                match action {
-                    Action::Sound(sound) => {
-                        sound.stop();
-                    }
-                    Action::Command(_) => {}
-                }
+                changed.extend(msg.event.paths);
             }
+        }
 
-            // unload all sounds
-            self.button_actions.clear();
+        let (board_changed, changed_assets) = self.changed_files(changed);
 
-            // parse new sounds
-            self.read_config_impl(&changed)?;
+        if board_changed || !changed_assets.is_empty() {
+            // parse new sounds, replaces the changed ones only if the board is fine
+            self.read_config_impl(&changed_assets)?;
 
             need_ligh_refresh = LightAction::ClearAndReapply;
         }
 
-        for (_btn_name, action) in &mut self.button_actions {
+        if self.finish_loads() && need_ligh_refresh == LightAction::None {
+            need_ligh_refresh = LightAction::Reapply;
+        }
+
+        let sound_system = &self.sound_system;
+        lock_or_return_err!(sound_system).update_ducking();
+
+        for action in self.button_actions.values_mut() {
             let result = action.update(&mut self.sound_system)?;
 
             match result {
@@ -569,15 +1569,31 @@ impl super::DeviceMode for SoundMode {
             }
         }
 
-        return Ok(need_ligh_refresh);
+        if self.level_lights {
+            let loud_slots = self.loud_slots();
+
+            if loud_slots != self.loud_slots {
+                self.loud_slots = loud_slots;
+
+                if need_ligh_refresh == LightAction::None {
+                    need_ligh_refresh = LightAction::Reapply;
+                }
+            }
+        }
+
+        Ok(need_ligh_refresh)
     }
 
-    fn display(&self, display: &mut push2_display::Push2Display) -> Result<(), MyError> {
+    fn display(&self, display: &mut FrameBuffer) -> Result<(), MyError> {
         self.display_sounds(display)?;
 
         Ok(())
     }
 
+    fn is_loading(&self) -> bool {
+        !self.loading.is_empty()
+    }
+
     fn encoder_change(
         &mut self,
         encoder_name: crate::button_map::EncoderName,
@@ -594,6 +1610,469 @@ impl super::DeviceMode for SoundMode {
             return Ok(LightAction::None);
         }
 
+        if let Some(index) = bus_encoder_index(encoder_name) {
+            let sound_system = &self.sound_system;
+            lock_or_return_err!(sound_system).change_bus_volume(index, change);
+
+            return Ok(LightAction::None);
+        }
+
         Ok(LightAction::None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use embedded_graphics::mock_display::MockDisplay;
+
+    use super::*;
+    use crate::{
+        audio::output::OutputBackend,
+        device_modes::DeviceMode,
+        frame_buffer::golden,
+        test_dir::{scene_config, sound_config, TestDir},
+    };
+
+    fn silent_sound_system() -> Arc<Mutex<SoundSystem>> {
+        Arc::new(Mutex::new(
+            SoundSystem::new(&OutputBackend::Null(1.0)).unwrap(),
+        ))
+    }
+
+    /// Writes a board with `actions` and opens it with every sound loaded.
+    fn open_board(dir: &TestDir, actions: &[String]) -> SoundMode {
+        let mut mode = SoundMode::new(silent_sound_system(), &dir.write_board(actions)).unwrap();
+        wait_for_loads(&mut mode);
+
+        mode
+    }
+
+    fn wait_for_loads(mode: &mut SoundMode) {
+        while mode.is_loading() {
+            mode.update().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Updates the mode until the sound of `note_name` is in `state`, or gives up after a while.
+    fn wait_for_state(
+        mode: &mut SoundMode,
+        note_name: NoteName,
+        state: ActionState,
+    ) -> ActionState {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+
+        while sound_state(mode, note_name) != state && std::time::Instant::now() < deadline {
+            mode.update().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        sound_state(mode, note_name)
+    }
+
+    fn playback_gain(mode: &SoundMode, note_name: NoteName) -> Option<f32> {
+        match mode
+            .button_actions
+            .get(&mode.current_slot(ButtonType::Note(note_name)))
+        {
+            Some(Action::Sound(sound)) => sound.playback_gain(),
+            _ => None,
+        }
+    }
+
+    /// Two loops, a one-shot and scenes on the pads after them.
+    ///
+    /// The loops fade quickly so the tests can wait for their fades to end.
+    fn scene_board(dir: &TestDir, scenes: &[&[(&str, f32)]]) -> SoundMode {
+        let quick_fades = ", fade_in_ms: 10, fade_out_ms: 10";
+        let scene_pads = ["Pad3x0", "Pad4x0", "Pad5x0"];
+
+        let mut actions = vec![
+            sound_config("Pad0x0", &dir.add_sound("a.wav"), true, quick_fades),
+            sound_config("Pad1x0", &dir.add_sound("b.wav"), true, quick_fades),
+            sound_config("Pad2x0", &dir.add_sound("c.wav"), false, ""),
+        ];
+        actions.extend(
+            scenes
+                .iter()
+                .zip(scene_pads)
+                .map(|(sounds, pad)| scene_config(pad, sounds)),
+        );
+
+        open_board(dir, &actions)
+    }
+
+    fn sound_state(mode: &SoundMode, note_name: NoteName) -> ActionState {
+        match mode
+            .button_actions
+            .get(&mode.current_slot(ButtonType::Note(note_name)))
+        {
+            Some(Action::Sound(sound)) => sound.is_running(),
+            _ => ActionState::None,
+        }
+    }
+
+    #[test]
+    fn idle_board_screen() {
+        let mut mode =
+            SoundMode::new(silent_sound_system(), Path::new("config/sound_config.ron")).unwrap();
+        wait_for_loads(&mut mode);
+
+        let mut frame = FrameBuffer::new();
+        mode.display_sounds(&mut frame).unwrap();
+
+        golden::assert_golden(&frame, "sound_mode_idle");
+    }
+
+    #[test]
+    fn follows_files_added_to_and_removed_from_a_variation_directory() {
+        let dir = TestDir::new("variations");
+        dir.add_sound("variations/a.wav");
+        let variations = dir.join("variations");
+
+        let mut mode = open_board(&dir, &[sound_config("Pad0x0", &variations, false, "")]);
+        let slot = mode.current_slot(ButtonType::Note(NoteName::Pad0x0));
+
+        // Only a sound with more than one file names its variations
+        let has_variations = |mode: &SoundMode| match mode.button_actions.get(&slot) {
+            Some(Action::Sound(sound)) => sound.variation_name().is_some(),
+            _ => false,
+        };
+
+        // What the watcher reports for a file, without waiting for it
+        let file_changed = |mode: &mut SoundMode, file: PathBuf| {
+            let (board_changed, changed_assets) = mode.changed_files(HashSet::from([file]));
+            mode.read_config_impl(&changed_assets).unwrap();
+            wait_for_loads(mode);
+
+            (board_changed, changed_assets)
+        };
+
+        assert!(!has_variations(&mode), "a single file has no variations");
+
+        let added = file_changed(&mut mode, dir.add_sound("variations/b.wav"));
+        assert!(!added.0 && added.1.contains(&variations), "{:?}", added);
+        assert!(has_variations(&mode), "the added file was not picked up");
+
+        std::fs::remove_file(variations.join("b.wav")).unwrap();
+        let removed = file_changed(&mut mode, variations.join("b.wav"));
+        assert!(
+            !removed.0 && removed.1.contains(&variations),
+            "{:?}",
+            removed
+        );
+        assert!(!has_variations(&mode), "the removed file is still played");
+    }
+
+    #[test]
+    fn a_reload_only_loads_the_changed_actions() {
+        let dir = TestDir::new("diffing_reload");
+        let looped = sound_config("Pad0x0", &dir.add_sound("a.wav"), true, "");
+        let one_shot = sound_config("Pad1x0", &dir.add_sound("b.wav"), false, "");
+
+        let mut mode = open_board(&dir, &[looped.clone(), one_shot.clone()]);
+        mode.button_press(NoteName::Pad0x0).unwrap();
+
+        let board = dir.write_board(&[looped, one_shot.replace("gain: 1.0", "gain: 0.5")]);
+        let (board_changed, changed_assets) = mode.changed_files(HashSet::from([board]));
+        assert!(board_changed);
+
+        mode.read_config_impl(&changed_assets).unwrap();
+        assert_eq!(mode.loads_queued, 1);
+        wait_for_loads(&mut mode);
+
+        // A reloaded loop would be a new sound that isn't playing
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::Playing);
+        assert_eq!(mode.load_errors, Vec::<String>::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn changes_behind_a_symlink_are_seen() {
+        let dir = TestDir::new("symlinked_sounds");
+        let file = dir.add_sound("sounds/a.wav");
+        std::os::unix::fs::symlink(dir.join("sounds"), dir.join("linked")).unwrap();
+
+        let mode = open_board(
+            &dir,
+            &[sound_config("Pad0x0", &dir.join("linked/a.wav"), false, "")],
+        );
+
+        // The watcher reports the file behind the link, and the other way round
+        let (_, changed_assets) = mode.changed_files(HashSet::from([file.clone()]));
+        assert_eq!(
+            changed_assets,
+            HashSet::from([file.canonicalize().unwrap()])
+        );
+
+        let (_, changed_assets) = mode.changed_files(HashSet::from([dir.join("linked/a.wav")]));
+        assert_eq!(
+            changed_assets,
+            HashSet::from([file.canonicalize().unwrap()])
+        );
+    }
+
+    /// A loop on Pad0x0 of the "Main" page from the top-level actions, and two more pages.
+    fn paged_board(dir: &TestDir) -> SoundMode {
+        let path = dir.join("board.ron");
+        let page = |name: &str, file: &str| {
+            format!(
+                "(name: {:?}, actions: [{}])",
+                name,
+                sound_config("Pad0x0", &dir.add_sound(file), true, "")
+            )
+        };
+
+        std::fs::write(
+            &path,
+            format!(
+                "ActionConfigs(actions: [{}], pages: [{}, {}])",
+                sound_config("Pad0x0", &dir.add_sound("main.wav"), true, ""),
+                page("Loops", "loops.wav"),
+                page("FX", "fx.wav")
+            ),
+        )
+        .unwrap();
+
+        let mut mode = SoundMode::new(silent_sound_system(), &path).unwrap();
+        wait_for_loads(&mut mode);
+
+        mode
+    }
+
+    #[test]
+    fn top_level_actions_become_the_main_page() {
+        let dir = TestDir::new("main_page");
+        let mode = paged_board(&dir);
+
+        assert_eq!(mode.pages, ["Main", "Loops", "FX"]);
+        assert_eq!(mode.current_page, 0);
+        assert_eq!(
+            mode.current_slot(ButtonType::Note(NoteName::Pad0x0)).page,
+            "Main"
+        );
+    }
+
+    #[test]
+    fn page_and_arrow_buttons_switch_pages() {
+        let dir = TestDir::new("page_buttons");
+        let mut mode = paged_board(&dir);
+
+        let mut press = |control_name: ControlName| {
+            let light_action = mode.control_press(control_name).unwrap();
+            (light_action, mode.pages[mode.current_page].clone())
+        };
+
+        assert_eq!(
+            press(ControlName::Control63),
+            (LightAction::ClearAndReapply, "Loops".to_string())
+        );
+        assert_eq!(
+            press(ControlName::Control45),
+            (LightAction::ClearAndReapply, "FX".to_string())
+        );
+        // Nothing after the last page
+        assert_eq!(
+            press(ControlName::Control45),
+            (LightAction::None, "FX".to_string())
+        );
+        assert_eq!(
+            press(ControlName::Control62),
+            (LightAction::ClearAndReapply, "Loops".to_string())
+        );
+        assert_eq!(
+            press(ControlName::Control44),
+            (LightAction::ClearAndReapply, "Main".to_string())
+        );
+        assert_eq!(
+            press(ControlName::Control44),
+            (LightAction::None, "Main".to_string())
+        );
+    }
+
+    #[test]
+    fn sounds_keep_playing_on_a_hidden_page() {
+        let dir = TestDir::new("hidden_page");
+        let mut mode = paged_board(&dir);
+
+        mode.button_press(NoteName::Pad0x0).unwrap();
+        mode.control_press(ControlName::Control63).unwrap();
+
+        // The same pad plays the sound of the page shown
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::None);
+        mode.button_press(NoteName::Pad0x0).unwrap();
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::Playing);
+
+        mode.control_press(ControlName::Control62).unwrap();
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::Playing);
+        assert_eq!(mode.playing_sound_names().len(), 2);
+    }
+
+    #[test]
+    fn scenes_fade_in_their_sounds_and_fade_out_other_loops() {
+        let dir = TestDir::new("scene_crossfade");
+        let mut mode = scene_board(&dir, &[&[("a", 1.0)]]);
+
+        mode.button_press(NoteName::Pad1x0).unwrap();
+        mode.button_press(NoteName::Pad2x0).unwrap();
+        mode.button_press(NoteName::Pad3x0).unwrap();
+
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::FadingIn);
+        assert_eq!(sound_state(&mode, NoteName::Pad1x0), ActionState::FadingOut);
+        // One-shots play to their end
+        assert_eq!(sound_state(&mode, NoteName::Pad2x0), ActionState::Playing);
+
+        assert_eq!(
+            wait_for_state(&mut mode, NoteName::Pad0x0, ActionState::Playing),
+            ActionState::Playing
+        );
+        assert_eq!(playback_gain(&mode, NoteName::Pad0x0), Some(1.0));
+        assert_eq!(
+            wait_for_state(&mut mode, NoteName::Pad1x0, ActionState::None),
+            ActionState::None
+        );
+    }
+
+    #[test]
+    fn scenes_change_the_gain_of_a_playing_sound() {
+        let dir = TestDir::new("scene_gain");
+        let mut mode = scene_board(&dir, &[&[("a", 0.5)]]);
+
+        mode.button_press(NoteName::Pad0x0).unwrap();
+        wait_for_state(&mut mode, NoteName::Pad0x0, ActionState::Playing);
+        assert_eq!(playback_gain(&mode, NoteName::Pad0x0), Some(1.0));
+
+        mode.button_press(NoteName::Pad3x0).unwrap();
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::FadingIn);
+
+        assert_eq!(
+            wait_for_state(&mut mode, NoteName::Pad0x0, ActionState::Playing),
+            ActionState::Playing
+        );
+        assert_eq!(playback_gain(&mode, NoteName::Pad0x0), Some(0.5));
+    }
+
+    #[test]
+    fn scenes_fade_out_sounds_set_to_silence_and_leave_idle_ones() {
+        let dir = TestDir::new("scene_silence");
+        let mut mode = scene_board(&dir, &[&[("a", 0.0), ("b", 0.0)]]);
+
+        mode.button_press(NoteName::Pad0x0).unwrap();
+        mode.button_press(NoteName::Pad3x0).unwrap();
+
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::FadingOut);
+        assert_eq!(sound_state(&mode, NoteName::Pad1x0), ActionState::None);
+
+        assert_eq!(
+            wait_for_state(&mut mode, NoteName::Pad0x0, ActionState::None),
+            ActionState::None
+        );
+    }
+
+    #[test]
+    fn scenes_choke_the_groups_of_their_sounds() {
+        let dir = TestDir::new("scene_choke");
+        let group = ", group: Some(\"music\")";
+
+        let mut mode = open_board(
+            &dir,
+            &[
+                sound_config("Pad0x0", &dir.add_sound("a.wav"), false, group),
+                sound_config("Pad1x0", &dir.add_sound("b.wav"), false, group),
+                scene_config("Pad2x0", &[("b", 1.0)]),
+            ],
+        );
+
+        mode.button_press(NoteName::Pad0x0).unwrap();
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::Playing);
+
+        mode.button_press(NoteName::Pad2x0).unwrap();
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::FadingOut);
+        assert_eq!(sound_state(&mode, NoteName::Pad1x0), ActionState::FadingIn);
+    }
+
+    #[test]
+    fn undecodable_files_are_reported_by_the_loader() {
+        let dir = TestDir::new("undecodable");
+        let broken = dir.join("broken.wav");
+        std::fs::write(&broken, b"not a wave file").unwrap();
+
+        let mode = open_board(&dir, &[sound_config("Pad0x0", &broken, false, "")]);
+
+        assert_eq!(mode.config_errors, Vec::<String>::new());
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::None);
+        assert_eq!(mode.load_errors.len(), 1);
+        assert!(
+            mode.load_errors[0].contains("can not be decoded"),
+            "{}",
+            mode.load_errors[0]
+        );
+    }
+
+    #[test]
+    fn a_sound_that_fails_to_reload_keeps_playing() {
+        let dir = TestDir::new("failed_reload");
+        let file = dir.add_sound("a.wav");
+        let mut mode = open_board(&dir, &[sound_config("Pad0x0", &file, true, "")]);
+
+        mode.button_press(NoteName::Pad0x0).unwrap();
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::Playing);
+
+        let reload = |mode: &mut SoundMode| {
+            let (_, changed_assets) = mode.changed_files(HashSet::from([file.clone()]));
+            mode.read_config_impl(&changed_assets).unwrap();
+            wait_for_loads(mode);
+        };
+
+        std::fs::write(&file, b"not a wave file").unwrap();
+        reload(&mut mode);
+
+        assert_eq!(mode.load_errors.len(), 1);
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::Playing);
+
+        // Once the file is fixed the new sound takes over
+        dir.add_sound("a.wav");
+        reload(&mut mode);
+
+        assert_eq!(mode.load_errors, Vec::<String>::new());
+        assert_eq!(sound_state(&mode, NoteName::Pad0x0), ActionState::None);
+    }
+
+    #[test]
+    fn the_peak_mark_turns_red_at_full_scale() {
+        let mark = |peak: f32| {
+            let mut display = MockDisplay::<Bgr565>::new();
+            SoundMode::draw_peak_mark(&mut display, Point::zero(), Size::new(2, 2), peak).unwrap();
+            display.get_pixel(Point::zero())
+        };
+
+        assert_eq!(mark(1.0), Some(Bgr565::RED));
+        assert_eq!(mark(0.99), Some(Bgr565::YELLOW));
+        assert_eq!(mark(0.0), None);
+    }
+
+    #[test]
+    fn text_ends_left_of_the_bus_volumes() {
+        let line = "x".repeat(100);
+
+        let clipped = clip_text(&line, 10, bus_area_left(3));
+        assert!(10 + clipped.len() as i32 * CHAR_WIDTH <= bus_area_left(3));
+        assert_eq!(clipped.len(), 71);
+
+        assert_eq!(clip_text("short", 10, bus_area_left(3)), "short");
+        assert_eq!(clip_text(&line, 900, bus_area_left(0)), "");
+    }
+
+    #[test]
+    fn volume_bar_after_turning_up() {
+        let sound_system = silent_sound_system();
+        sound_system.lock().unwrap().change_volume(20);
+
+        let mut frame = FrameBuffer::new();
+        SoundMode::draw_volume(&sound_system, &mut frame).unwrap();
+
+        golden::assert_golden(&frame, "volume_bar");
+    }
+}
@@ -44,10 +44,7 @@ impl SpotifyMode {
     pub fn new() -> Result<SpotifyMode, MyError> {
         let spotify = spotify::Spotify::new()?;
 
-        let (thread_sender, main_receiver) = channel();
-        let (main_sender, thread_receiver) = channel();
-
-        let handle = thread::spawn(move || {
+        Ok(SpotifyMode::with_worker(move |thread_receiver, thread_sender| {
             while let Ok(msg) = thread_receiver.recv() {
                 match msg {
                     Query::CurrentSong(_) => {
@@ -98,11 +95,21 @@ impl SpotifyMode {
                     }
                 }
             }
+        }))
+    }
 
-            return ();
-        });
+    /// Runs `worker` on its own thread, it answers the queries of the mode on the sender it is
+    /// given.
+    fn with_worker<F>(worker: F) -> SpotifyMode
+    where
+        F: FnOnce(Receiver<Query>, Sender<Query>) + Send + 'static,
+    {
+        let (thread_sender, main_receiver) = channel();
+        let (main_sender, thread_receiver) = channel();
 
-        Ok(SpotifyMode {
+        let handle = thread::spawn(move || worker(thread_receiver, thread_sender));
+
+        SpotifyMode {
             last_updated: std::time::Instant::now(),
             _worker_thread: handle,
             playing_song: None,
@@ -110,7 +117,7 @@ impl SpotifyMode {
             selected_playlist: 0,
             sender: main_sender,
             receiver: main_receiver,
-        })
+        }
     }
 }
 
@@ -193,12 +200,12 @@ impl super::DeviceMode for SpotifyMode {
                     .expect("Could not send query to thread.");
             }
             crate::button_map::NoteName::Pad3x0 => {
-                if let Some(playlists) = &self.playlists {
-                    if playlists.len() > self.selected_playlist {
-                        self.sender
-                            .send(Query::PlayPlaylist(Some(playlists[self.selected_playlist].clone())))
-                            .expect("Could not send query to thread.");
-                    }
+                if let Some(playlists) = &self.playlists
+                    && playlists.len() > self.selected_playlist
+                {
+                    self.sender
+                        .send(Query::PlayPlaylist(Some(playlists[self.selected_playlist].clone())))
+                        .expect("Could not send query to thread.");
                 }
                 self.sender
                     .send(Query::CurrentSong(None))
@@ -223,7 +230,7 @@ impl super::DeviceMode for SpotifyMode {
 
     fn apply_button_lights(
         &mut self,
-        midiconn: &std::sync::Arc<std::sync::Mutex<crate::midi::MidiConnection>>,
+        midiconn: &crate::midi::SharedMidi,
         button_values: &std::collections::HashMap<u8, crate::button_map::ButtonType>,
     ) -> Result<(), MyError> {
         // Do nothing for now
@@ -347,7 +354,7 @@ impl super::DeviceMode for SpotifyMode {
         Ok(super::LightAction::None)
     }
 
-    fn display(&self, display: &mut push2_display::Push2Display) -> Result<(), MyError> {
+    fn display(&self, display: &mut crate::frame_buffer::FrameBuffer) -> Result<(), MyError> {
         if let Some(song) = &self.playing_song {
             Text::new(
                 song,
@@ -419,3 +426,91 @@ impl super::DeviceMode for SpotifyMode {
        
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::{
+        button_map::{ButtonType, NoteName},
+        device_modes::DeviceMode,
+        frame_buffer::{golden, FrameBuffer},
+        midi::{SharedMidi, VirtualMidi, CONTROL_CHANGE, NOTE_ON},
+    };
+
+    /// A mode answering every query right away instead of asking the Web API.
+    fn offline_mode(song: &'static str) -> SpotifyMode {
+        SpotifyMode::with_worker(move |queries, replies| {
+            while let Ok(query) = queries.recv() {
+                let reply = match query {
+                    Query::CurrentSong(_) => Query::CurrentSong(Some(song.to_string())),
+                    Query::CurrentUserPlaylists(_) => Query::CurrentUserPlaylists(Some(vec![])),
+                    other => other,
+                };
+
+                if replies.send(reply).is_err() {
+                    return;
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn lights_the_transport_pads() {
+        let mut mode = offline_mode("Test song");
+        let virtual_midi = Arc::new(Mutex::new(VirtualMidi::new()));
+        let midiconn: SharedMidi = virtual_midi.clone();
+
+        let button_values = HashMap::from([
+            (36, ButtonType::Note(NoteName::Pad0x0)),
+            (39, ButtonType::Note(NoteName::Pad3x0)),
+            (40, ButtonType::Note(NoteName::Pad4x0)),
+            (71, ButtonType::Encoder(EncoderName::Control71)),
+        ]);
+
+        mode.apply_button_lights(&midiconn, &button_values).unwrap();
+
+        let midi = virtual_midi.lock().unwrap();
+        assert_eq!(midi.light(NOTE_ON, 36), 124);
+        assert_eq!(midi.light(NOTE_ON, 39), 124);
+        assert_eq!(midi.light(NOTE_ON, 40), 0);
+        assert_eq!(midi.light(CONTROL_CHANGE, 71), 122);
+    }
+
+    /// Updates the mode until the worker's answer about the playing song arrived.
+    fn wait_for_song(mode: &mut SpotifyMode) {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while mode.playing_song.is_none() && Instant::now() < deadline {
+            mode.update().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn skip_asks_for_the_new_song() {
+        let mut mode = offline_mode("Test song");
+
+        mode.button_press(NoteName::Pad2x0).unwrap();
+        wait_for_song(&mut mode);
+
+        assert_eq!(mode.playing_song.as_deref(), Some("Test song"));
+    }
+
+    #[test]
+    fn screen_shows_the_playing_song() {
+        let mut mode = offline_mode("Test song");
+
+        mode.button_press(NoteName::Pad2x0).unwrap();
+        wait_for_song(&mut mode);
+
+        let mut frame = FrameBuffer::new();
+        mode.display(&mut frame).unwrap();
+
+        golden::assert_golden(&frame, "spotify_mode_song");
+    }
+}
@@ -0,0 +1,326 @@
+use std::{fs::File, io::Read, path::Path};
+
+use regex::Regex;
+
+use crate::{midi::MidiConnection, sound_system::SoundSystem, MyError};
+
+#[derive(Deserialize)]
+pub struct DeviceConfig {
+    pub sound_device: String,
+    pub midi_in: String,
+    pub midi_out: String,
+
+    #[serde(default)]
+    pub matching: DeviceMatching,
+}
+
+impl DeviceConfig {
+    pub fn load(path: &Path) -> Result<DeviceConfig, MyError> {
+        let mut file = match File::open(path) {
+            Ok(handle) => handle,
+            Err(_err) => return Err(MyError::ConfigFileNotFound("Devices")),
+        };
+
+        let mut config_string = String::new();
+
+        if file.read_to_string(&mut config_string).is_err() {
+            return Err(MyError::ConfigFileReadError);
+        }
+
+        Ok(ron::de::from_str(&config_string)?)
+    }
+
+    pub fn sound_device_matcher(&self) -> Result<DeviceMatcher, MyError> {
+        DeviceMatcher::new(&self.sound_device, self.matching)
+    }
+
+    pub fn midi_in_matcher(&self) -> Result<DeviceMatcher, MyError> {
+        DeviceMatcher::new(&self.midi_in, self.matching)
+    }
+
+    pub fn midi_out_matcher(&self) -> Result<DeviceMatcher, MyError> {
+        DeviceMatcher::new(&self.midi_out, self.matching)
+    }
+}
+
+/// How the device names in devices.ron are compared to the names of the available devices.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum DeviceMatching {
+    #[default]
+    Exact,
+    Substring,
+    Regex,
+}
+
+#[derive(Clone)]
+pub struct DeviceMatcher {
+    pattern: String,
+    regex: Option<Regex>,
+    matching: DeviceMatching,
+}
+
+impl DeviceMatcher {
+    pub fn new(pattern: &str, matching: DeviceMatching) -> Result<DeviceMatcher, MyError> {
+        let regex = match matching {
+            DeviceMatching::Regex => Some(Regex::new(pattern)?),
+            DeviceMatching::Exact | DeviceMatching::Substring => None,
+        };
+
+        Ok(DeviceMatcher {
+            pattern: pattern.to_string(),
+            regex,
+            matching,
+        })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self.matching {
+            DeviceMatching::Exact => name == self.pattern,
+            DeviceMatching::Substring => name.contains(&self.pattern),
+            DeviceMatching::Regex => self
+                .regex
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(name)),
+        }
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+}
+
+/// Everything the soundboard could connect to.
+///
+/// A kind of device that could not be listed keeps the error instead, so the others still show.
+pub struct DeviceList {
+    pub midi_inputs: Result<Vec<String>, String>,
+    pub midi_outputs: Result<Vec<String>, String>,
+    pub audio_outputs: Result<Vec<String>, String>,
+    // Opened when no audio output matches the config
+    pub default_audio_output: Option<String>,
+}
+
+impl DeviceList {
+    pub fn discover() -> DeviceList {
+        DeviceList {
+            midi_inputs: MidiConnection::input_port_names().map_err(|err| err.to_string()),
+            midi_outputs: MidiConnection::output_port_names().map_err(|err| err.to_string()),
+            audio_outputs: SoundSystem::output_device_names().map_err(|err| err.to_string()),
+            default_audio_output: SoundSystem::default_output_device_name(),
+        }
+    }
+
+    pub fn print(&self, config: Option<&DeviceConfig>) {
+        for line in self.lines(config) {
+            println!("{}", line);
+        }
+    }
+
+    /// All devices, marking the ones the device config would pick with a `*`.
+    fn lines(&self, config: Option<&DeviceConfig>) -> Vec<String> {
+        let sections = [
+            (
+                "Midi inputs",
+                &self.midi_inputs,
+                config.map(DeviceConfig::midi_in_matcher),
+                None,
+            ),
+            (
+                "Midi outputs",
+                &self.midi_outputs,
+                config.map(DeviceConfig::midi_out_matcher),
+                None,
+            ),
+            (
+                "Audio outputs",
+                &self.audio_outputs,
+                config.map(DeviceConfig::sound_device_matcher),
+                self.default_audio_output.as_deref(),
+            ),
+        ];
+
+        let mut lines = vec![];
+
+        for (title, names, matcher, fallback) in sections {
+            lines.push(format!("{}:", title));
+
+            let names = match names {
+                Ok(value) => value,
+                Err(err) => {
+                    lines.push(format!("  could not be listed: {}", err));
+                    continue;
+                }
+            };
+
+            let matcher = match matcher {
+                Some(Ok(value)) => Some(value),
+                Some(Err(err)) => {
+                    lines.push(format!("  could not match the config: {}", err));
+                    None
+                }
+                None => None,
+            };
+
+            // Only the audio output falls back to a default when nothing matches
+            let (chosen, fell_back) = match matcher.map(|matcher| first_match(&matcher, names)) {
+                Some(Some(name)) => (Some(name), false),
+                Some(None) => (fallback, true),
+                None => (None, false),
+            };
+
+            for name in names.iter() {
+                if chosen == Some(name.as_str()) {
+                    let note = if fell_back {
+                        " (default, nothing matches)"
+                    } else {
+                        ""
+                    };
+                    lines.push(format!("  * {}{}", name, note));
+                } else {
+                    lines.push(format!("    {}", name));
+                }
+            }
+        }
+
+        lines
+    }
+}
+
+/// The first of `names` the matcher accepts, this is the one that gets opened.
+pub fn first_match<'a>(matcher: &DeviceMatcher, names: &'a [String]) -> Option<&'a str> {
+    names
+        .iter()
+        .find(|name| matcher.matches(name))
+        .map(|name| name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn config(sound_device: &str, matching: DeviceMatching) -> DeviceConfig {
+        DeviceConfig {
+            sound_device: sound_device.to_string(),
+            midi_in: "Ableton Push 2".to_string(),
+            midi_out: "Ableton Push 2".to_string(),
+            matching,
+        }
+    }
+
+    fn device_list() -> DeviceList {
+        DeviceList {
+            midi_inputs: Ok(names(&["Midi Through", "Ableton Push 2"])),
+            midi_outputs: Ok(names(&["Ableton Push 2"])),
+            audio_outputs: Ok(names(&["Speakers", "Headphones"])),
+            default_audio_output: Some("Speakers".to_string()),
+        }
+    }
+
+    #[test]
+    fn exact_matching_needs_the_whole_name() {
+        let matcher = DeviceMatcher::new("Push 2", DeviceMatching::Exact).unwrap();
+
+        assert!(matcher.matches("Push 2"));
+        assert!(!matcher.matches("Ableton Push 2"));
+        assert!(!matcher.matches("push 2"));
+    }
+
+    #[test]
+    fn substring_matching_finds_the_name_anywhere() {
+        let matcher = DeviceMatcher::new("Push 2", DeviceMatching::Substring).unwrap();
+
+        assert!(matcher.matches("Push 2"));
+        assert!(matcher.matches("Ableton Push 2:0"));
+        assert!(!matcher.matches("Ableton Push"));
+    }
+
+    #[test]
+    fn regex_matching_uses_the_pattern() {
+        let matcher =
+            DeviceMatcher::new("^Ableton Push 2( MIDI 1)?$", DeviceMatching::Regex).unwrap();
+
+        assert!(matcher.matches("Ableton Push 2"));
+        assert!(matcher.matches("Ableton Push 2 MIDI 1"));
+        assert!(!matcher.matches("Ableton Push 2 MIDI 2"));
+
+        assert!(DeviceMatcher::new("Push (2", DeviceMatching::Regex).is_err());
+    }
+
+    #[test]
+    fn the_first_matching_name_is_picked() {
+        let matcher = DeviceMatcher::new("Push 2", DeviceMatching::Substring).unwrap();
+        let ports = names(&[
+            "Midi Through",
+            "Ableton Push 2 MIDI 1",
+            "Ableton Push 2 MIDI 2",
+        ]);
+
+        assert_eq!(first_match(&matcher, &ports), Some("Ableton Push 2 MIDI 1"));
+        assert_eq!(first_match(&matcher, &names(&["Midi Through"])), None);
+    }
+
+    #[test]
+    fn the_listing_marks_the_picked_devices() {
+        let lines = device_list().lines(Some(&config("Headphones", DeviceMatching::Exact)));
+
+        assert_eq!(
+            lines,
+            [
+                "Midi inputs:",
+                "    Midi Through",
+                "  * Ableton Push 2",
+                "Midi outputs:",
+                "  * Ableton Push 2",
+                "Audio outputs:",
+                "    Speakers",
+                "  * Headphones",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_listing_marks_the_default_audio_output_when_nothing_matches() {
+        let lines = device_list().lines(Some(&config("Monitors", DeviceMatching::Exact)));
+
+        assert_eq!(
+            lines[5..],
+            [
+                "Audio outputs:",
+                "  * Speakers (default, nothing matches)",
+                "    Headphones",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_listing_reports_errors_per_section() {
+        let mut list = device_list();
+        list.audio_outputs = Err("no audio host".to_string());
+
+        let lines = list.lines(Some(&config("Speakers", DeviceMatching::Exact)));
+        assert_eq!(
+            lines[3..],
+            [
+                "Midi outputs:",
+                "  * Ableton Push 2",
+                "Audio outputs:",
+                "  could not be listed: no audio host"
+            ]
+        );
+
+        // A broken pattern only loses the marks of its own section
+        let lines = device_list().lines(Some(&config("Speakers (", DeviceMatching::Regex)));
+        assert_eq!(lines[4], "  * Ableton Push 2");
+        assert_eq!(lines[5], "Audio outputs:");
+        assert!(
+            lines[6].starts_with("  could not match the config: "),
+            "{}",
+            lines[6]
+        );
+        assert_eq!(lines[7..], ["    Speakers", "    Headphones"]);
+    }
+}
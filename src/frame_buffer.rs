@@ -0,0 +1,164 @@
+use std::{convert::Infallible, fs::File, io::BufWriter, path::Path};
+
+use embedded_graphics::{
+    pixelcolor::Bgr565,
+    prelude::{Dimensions, DrawTarget, OriginDimensions, Pixel, RgbColor, Size},
+};
+
+use crate::MyError;
+
+pub const DISPLAY_WIDTH: u32 = 960;
+pub const DISPLAY_HEIGHT: u32 = 160;
+
+/// Off-screen copy of the Push2 display.
+///
+/// Device modes draw into this buffer, which is then copied to the hardware display, a
+/// simulator window or a PNG file.
+pub struct FrameBuffer {
+    pixels: Vec<Bgr565>,
+}
+
+impl FrameBuffer {
+    pub fn new() -> FrameBuffer {
+        FrameBuffer {
+            pixels: vec![Bgr565::BLACK; (DISPLAY_WIDTH * DISPLAY_HEIGHT) as usize],
+        }
+    }
+
+    /// Copies the whole frame to another draw target, e.g. the `Push2Display`.
+    pub fn draw_to<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Bgr565>,
+    {
+        target.fill_contiguous(&self.bounding_box(), self.pixels.iter().copied())
+    }
+
+    /// The frame as tightly packed 8-bit RGB triples.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 3);
+
+        for color in &self.pixels {
+            // Scale the 5/6/5 bit channels up to the full 8 bit range
+            data.push((color.r() << 3) | (color.r() >> 2));
+            data.push((color.g() << 2) | (color.g() >> 4));
+            data.push((color.b() << 3) | (color.b() >> 2));
+        }
+
+        data
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), MyError> {
+        let file = File::create(path)?;
+
+        let mut encoder = png::Encoder::new(BufWriter::new(file), DISPLAY_WIDTH, DISPLAY_HEIGHT);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb8())?;
+
+        Ok(())
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        FrameBuffer::new()
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        Size::new(DISPLAY_WIDTH, DISPLAY_HEIGHT)
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = Bgr565;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x >= 0
+                && point.y >= 0
+                && (point.x as u32) < DISPLAY_WIDTH
+                && (point.y as u32) < DISPLAY_HEIGHT
+            {
+                self.pixels[(point.y as u32 * DISPLAY_WIDTH + point.x as u32) as usize] = color;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Golden images of the display, kept in `tests/golden`.
+///
+/// Run the tests with `UPDATE_GOLDEN=1` to write the current frames as the new golden images
+/// after a deliberate change to a screen.
+#[cfg(test)]
+pub mod golden {
+    use std::{fs::File, path::PathBuf};
+
+    use super::FrameBuffer;
+
+    fn golden_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("golden")
+            .join(format!("{}.png", name))
+    }
+
+    fn read_rgb8(path: &PathBuf) -> Vec<u8> {
+        let file = File::open(path).unwrap_or_else(|err| {
+            panic!(
+                "Could not open {}, run with UPDATE_GOLDEN=1 to create it: {}",
+                path.display(),
+                err
+            )
+        });
+
+        let mut reader = png::Decoder::new(file).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        data.truncate(info.buffer_size());
+
+        data
+    }
+
+    /// Fails if `frame` differs from the golden image `name`, the frame is saved to the temp
+    /// directory to compare them.
+    pub fn assert_golden(frame: &FrameBuffer, name: &str) {
+        let path = golden_path(name);
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            frame.save_png(&path).unwrap();
+            return;
+        }
+
+        let expected = read_rgb8(&path);
+        let actual = frame.to_rgb8();
+
+        if expected != actual {
+            let actual_path = std::env::temp_dir().join(format!("{}.actual.png", name));
+            frame.save_png(&actual_path).unwrap();
+
+            let differing = expected
+                .chunks(3)
+                .zip(actual.chunks(3))
+                .filter(|(expected, actual)| expected != actual)
+                .count();
+
+            panic!(
+                "{} pixels differ from {}, the frame is in {}",
+                differing,
+                path.display(),
+                actual_path.display()
+            );
+        }
+    }
+}
@@ -4,8 +4,16 @@
 //)]
 
 use anyhow::Result;
+use audio::output::OutputBackend;
 use button_map::ButtonMap;
-use midi::MidiConnection;
+use clap::Parser;
+use cli::Cli;
+use discovery::{DeviceConfig, DeviceList};
+use frame_buffer::FrameBuffer;
+use midi::{SharedMidi, VirtualMidi};
+use push2::Push2Connection;
+use session::RecordingMidi;
+use simulator::Simulator;
 use sound_system::SoundSystem;
 use thiserror::Error;
 
@@ -17,16 +25,25 @@ use push2_display::*;
 use embedded_graphics::{pixelcolor::Bgr565, prelude::*};
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
-use std::{convert::Infallible, fs::File, io::Read};
+use std::convert::Infallible;
 use tray_item::TrayItem;
 
 use crate::midi::MidiMessage;
 
 mod actions;
+mod audio;
 mod button_map;
+mod cli;
 mod device_modes;
+mod discovery;
+mod frame_buffer;
 mod midi;
+mod push2;
+mod session;
+mod simulator;
 mod sound_system;
+#[cfg(test)]
+mod test_dir;
 
 #[cfg(feature = "spotify")]
 mod spotify;
@@ -64,6 +81,9 @@ pub enum MyError {
     #[error("FileWatcher error")]
     FileWatcher(&'static str),
 
+    #[error("Replay diverged from the recording before input {0}")]
+    ReplayMismatch(usize),
+
     #[cfg(feature = "spotify")]
     #[error(transparent)]
     SpotifyError(#[from] rspotify::ClientError),
@@ -98,6 +118,24 @@ pub enum MyError {
     #[error(transparent)]
     MidirError5(#[from] SendError),
 
+    #[error(transparent)]
+    RonError(#[from] ron::Error),
+
+    #[error(transparent)]
+    RonParseError(#[from] ron::de::SpannedError),
+
+    #[error(transparent)]
+    RegexError(#[from] regex::Error),
+
+    #[error(transparent)]
+    PngError(#[from] png::EncodingError),
+
+    #[error(transparent)]
+    WavError(#[from] hound::Error),
+
+    #[error(transparent)]
+    SimulatorError(#[from] minifb::Error),
+
     #[error(transparent)]
     Infallible(#[from] Infallible),
 
@@ -108,13 +146,6 @@ pub enum MyError {
 const MAX_VOLUME: u32 = 400;
 const DEFAULT_VOLUME: u32 = 100;
 
-#[derive(Deserialize)]
-struct DeviceConfig {
-    sound_device: String,
-    midi_in: String,
-    midi_out: String,
-}
-
 #[macro_export]
 macro_rules! lock_or_return_err {
     ($mutex:ident) => {{
@@ -129,25 +160,83 @@ macro_rules! lock_or_return_err {
 }
 
 fn run() -> Result<(), MyError> {
-    let mut file = File::open("config/devices.ron")?;
-    let mut config_string = String::new();
-    file.read_to_string(&mut config_string)
-        .expect("Could not read config file.");
+    let cli = Cli::parse();
 
-    let device_config: DeviceConfig =
-        ron::de::from_str(&config_string).expect("Could not deserialize DeviceConfig.");
+    let device_config = DeviceConfig::load(&cli.device_config_path()).map(|mut config| {
+        if let Some(sound_device) = &cli.sound_device {
+            config.sound_device = sound_device.clone();
+        }
+        if let Some(midi_in) = &cli.midi_in {
+            config.midi_in = midi_in.clone();
+        }
+        if let Some(midi_out) = &cli.midi_out {
+            config.midi_out = midi_out.clone();
+        }
+        config
+    });
 
-    let mut display = Push2Display::new()?;
+    if cli.list_devices {
+        // Listing works without a device config, it just can't mark the chosen devices then
+        DeviceList::discover().print(device_config.as_ref().ok());
+        return Ok(());
+    }
 
-    let (push2midi, receiver) =
-        MidiConnection::new(&device_config.midi_in, &device_config.midi_out)?;
+    let device_config = device_config?;
+
+    let audio_output = if let Some(path) = &cli.render_wav {
+        OutputBackend::WavFile(path.clone(), cli.render_speed)
+    } else if cli.null_audio {
+        OutputBackend::Null(cli.render_speed)
+    } else {
+        OutputBackend::Device(device_config.sound_device_matcher()?)
+    };
+
+    if let Some(replay_path) = &cli.replay {
+        let sound_system = Arc::new(Mutex::new(SoundSystem::new(&audio_output)?));
+        let result = session::replay_file(
+            replay_path,
+            Arc::clone(&sound_system),
+            &cli.button_values_path(),
+            &cli.board_path(),
+        );
+        lock_or_return_err!(sound_system).shutdown()?;
+        return result;
+    }
 
-    let mut push2midi = Arc::new(Mutex::new(push2midi));
+    let mut frame = FrameBuffer::new();
+    let mut push2 = None;
+    let mut simulator = None;
+
+    let push2midi: SharedMidi = if cli.simulate {
+        let virtual_midi = Arc::new(Mutex::new(VirtualMidi::new()));
+        simulator = Some(Simulator::new(Arc::clone(&virtual_midi))?);
+        virtual_midi
+    } else {
+        let connection = Push2Connection::open(
+            device_config.midi_in_matcher()?,
+            device_config.midi_out_matcher()?,
+        )?;
+        let midi = connection.midi();
+        push2 = Some(connection);
+        midi
+    };
+
+    let mut recorder = None;
+    let push2midi: SharedMidi = match &cli.record {
+        Some(path) => {
+            let recording = Arc::new(Mutex::new(RecordingMidi::new(push2midi, path.clone())));
+            recorder = Some(Arc::clone(&recording));
+            recording
+        }
+        None => push2midi,
+    };
 
-    let sound_system = Arc::new(Mutex::new(SoundSystem::new(&device_config.sound_device)?));
+    let sound_system = Arc::new(Mutex::new(SoundSystem::new(&audio_output)?));
 
     let button_mapping = Arc::new(Mutex::new(ButtonMap::new(
         Arc::clone(&sound_system),
+        &cli.button_values_path(),
+        &cli.board_path(),
         &push2midi,
     )?));
 
@@ -173,19 +262,45 @@ fn run() -> Result<(), MyError> {
         for action in game_loop.actions() {
             match action {
                 FrameAction::Tick => {
-                    while let Ok(msg) = receiver.try_recv() {
+                    if let Some(push2) = &mut push2
+                        && push2.update()?
+                    {
+                        lock_or_return_err!(button_mapping).clear_button_lights(&push2midi)?;
+                        lock_or_return_err!(button_mapping).apply_button_lights(&push2midi)?;
+                    }
+
+                    loop {
+                        // The midi lock must be released before the button map uses it again.
+                        let msg = lock_or_return_err!(push2midi).receive();
+
                         match msg {
-                            MidiMessage::Input(address, value) => {
+                            Some(MidiMessage::Input(address, value)) => {
                                 lock_or_return_err!(button_mapping)
                                     .activate_button(address, value, &push2midi)?
                             }
+                            None => break,
                         }
                     }
 
-                    lock_or_return_err!(button_mapping).update(&mut push2midi)?;
+                    lock_or_return_err!(button_mapping).update(&push2midi)?;
+
+                    if let Some(recorder) = &recorder
+                        && !lock_or_return_err!(button_mapping).is_loading()
+                    {
+                        lock_or_return_err!(recorder).start_clock();
+                    }
 
-                    if atomic_flag.load(std::sync::atomic::Ordering::SeqCst) {
+                    let simulator_closed = simulator.as_ref().is_some_and(|sim| !sim.is_open());
+
+                    if atomic_flag.load(std::sync::atomic::Ordering::SeqCst) || simulator_closed {
                         lock_or_return_err!(button_mapping).clear_button_lights(&push2midi)?;
+
+                        if let Some(recorder) = &recorder {
+                            lock_or_return_err!(recorder).save()?;
+                        }
+
+                        lock_or_return_err!(sound_system).shutdown()?;
+
                         std::process::exit(0);
                     }
                 }
@@ -193,11 +308,17 @@ fn run() -> Result<(), MyError> {
                 FrameAction::Render {
                     interpolation: _interpolation,
                 } => {
-                    display.clear(Bgr565::BLACK)?;
+                    frame.clear(Bgr565::BLACK)?;
+
+                    lock_or_return_err!(button_mapping).display(&mut frame)?;
 
-                    lock_or_return_err!(button_mapping).display(&mut display)?;
+                    if let Some(push2) = &mut push2 {
+                        push2.draw(&frame)?;
+                    }
 
-                    display.flush()?;
+                    if let Some(simulator) = &mut simulator {
+                        simulator.update(&frame)?;
+                    }
                 }
             }
         }
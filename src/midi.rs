@@ -1,24 +1,88 @@
+use std::collections::{HashMap, VecDeque};
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
 
 use midir::{
     Ignore, MidiInput, MidiInputConnection, MidiInputPort, MidiOutput, MidiOutputConnection,
     MidiOutputPort,
 };
 
-use crate::MyError;
+use crate::{discovery::DeviceMatcher, MyError};
 use std::sync::mpsc::channel;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MidiMessage {
     Input(u8, i16),
 }
 
-const CONTROL_CHANGE: u8 = 0xB0;
-const NOTE_ON: u8 = 0x90;
+pub const CONTROL_CHANGE: u8 = 0xB0;
+pub const NOTE_ON: u8 = 0x90;
+
+/// Whether the control change `address` is one of the Push2 encoders, which send relative values.
+fn is_encoder(address: u8) -> bool {
+    matches!(address, 14 | 15 | 71..=79)
+}
+
+/// A MIDI device the soundboard can talk to.
+///
+/// Everything that drives the Push2 lights or reads its buttons goes through this trait, so the
+/// app can run against the real hardware (`MidiConnection`) or an in-memory device (`VirtualMidi`).
+pub trait MidiBackend: Send {
+    fn send_to_device(&mut self, data: &[u8]) -> Result<(), MyError>;
+
+    /// Returns the next pending input message, if any.
+    fn receive(&mut self) -> Option<MidiMessage>;
+}
+
+pub type SharedMidi = Arc<Mutex<dyn MidiBackend>>;
+
+/// Last colour sent per (status byte, address).
+pub type LightState = HashMap<(u8, u8), u8>;
+
+/// Folds a message sent to the device into the light state it results in.
+pub fn apply_light_message(lights: &mut LightState, data: &[u8]) {
+    if let [status @ (CONTROL_CHANGE | NOTE_ON), address, value] = data {
+        lights.insert((*status, *address), *value);
+    }
+}
+
+impl MidiMessage {
+    /// Translates a raw MIDI message from the Push2 into a `MidiMessage`.
+    pub fn parse(message: &[u8]) -> Option<MidiMessage> {
+        match message {
+            [CONTROL_CHANGE, address, value] => {
+                if is_encoder(*address) {
+                    Some(MidiMessage::Input(
+                        *address,
+                        MidiMessage::get_endcoder_value(value),
+                    ))
+                } else {
+                    Some(MidiMessage::Input(*address, (*value) as i16))
+                }
+            }
+            [NOTE_ON, address, value] => Some(MidiMessage::Input(*address, (*value) as i16)),
+            _ => {
+                // println!("{}: {:X?} (len = {})", stamp, message, message.len());
+                None
+            }
+        }
+    }
+
+    fn get_endcoder_value(value: &u8) -> i16 {
+        let is_right: bool = (value & 0xC0) == 0;
+        if is_right {
+            (value & 0x3F) as i16
+        } else {
+            -(64 - ((value & 0x3F) as i16))
+        }
+    }
+}
 
 #[allow(dead_code)]
 pub struct MidiConnection {
     in_port: MidiInputPort,
     in_conn: MidiInputConnection<Sender<MidiMessage>>,
+    receiver: Receiver<MidiMessage>,
 
     out_port: MidiOutputPort,
     out_conn: MidiOutputConnection,
@@ -26,15 +90,15 @@ pub struct MidiConnection {
 
 impl MidiConnection {
     pub fn new(
-        in_name: &str,
-        out_name: &str,
-    ) -> Result<(MidiConnection, Receiver<MidiMessage>), MyError> {
+        in_matcher: &DeviceMatcher,
+        out_matcher: &DeviceMatcher,
+    ) -> Result<MidiConnection, MyError> {
         let mut midi_in = MidiInput::new("Push2_Soundboard-IN")?;
         midi_in.ignore(Ignore::None);
-        let in_port = MidiConnection::get_midi_in_port(&midi_in, in_name)?;
+        let in_port = MidiConnection::get_midi_in_port(&midi_in, in_matcher)?;
 
         let midi_out = MidiOutput::new("Push2_Soundboard-OUT")?;
-        let out_port = MidiConnection::get_midi_out_port(&midi_out, out_name)?;
+        let out_port = MidiConnection::get_midi_out_port(&midi_out, out_matcher)?;
         let conn_out = midi_out.connect(&out_port, "Push2_Soundboard-OutPort")?;
 
         let (tx1, rx1) = channel::<MidiMessage>();
@@ -43,76 +107,186 @@ impl MidiConnection {
             &in_port,
             "Push2_Soundboard-InPort",
             move |_, message, tx| {
-                match message {
-                    [CONTROL_CHANGE, address, value] => {                        
-                        if address == &0x4E || address == &0x47 {
-                            tx.send(MidiMessage::Input(
-                                *address,
-                                MidiConnection::get_endcoder_value(value),
-                            ))
-                            .expect("Could not send volume message through channel")
-                        } else {
-                            tx.send(MidiMessage::Input(*address, (*value) as i16))
-                                .expect("Could not send control change message through channel.");
-                        }
-                    }
-                    [NOTE_ON, address, value] => {
-                        tx.send(MidiMessage::Input(*address, (*value) as i16))
-                            .expect("Could not send note on message through channel");
-                    }
-                    _ => {
-                        // println!("{}: {:X?} (len = {})", stamp, message, message.len());
-                    }
+                if let Some(msg) = MidiMessage::parse(message) {
+                    tx.send(msg)
+                        .expect("Could not send midi message through channel.");
                 }
             },
             tx1,
         )?;
 
-        Ok((
-            MidiConnection {
-                in_port,
-                in_conn: conn_in,
-                out_port,
-                out_conn: conn_out,
-            },
-            rx1,
-        ))
+        Ok(MidiConnection {
+            in_port,
+            in_conn: conn_in,
+            receiver: rx1,
+            out_port,
+            out_conn: conn_out,
+        })
     }
 
-    pub fn send_to_device(&mut self, data: &[u8]) -> Result<(), MyError> {
-        self.out_conn.send(data)?;
-        Ok(())
+    pub fn input_port_names() -> Result<Vec<String>, MyError> {
+        let midi_in = MidiInput::new("Push2_Soundboard-IN")?;
+
+        Ok(midi_in
+            .ports()
+            .iter()
+            .filter_map(|port| midi_in.port_name(port).ok())
+            .collect())
     }
 
-    fn get_endcoder_value(value: &u8) -> i16 {
-        let is_right: bool = (value & 0xC0) == 0;
-        if is_right {
-            (value & 0x3F) as i16
-        } else {
-            (64 - ((value & 0x3F) as i16)) * -1
-        }
+    pub fn output_port_names() -> Result<Vec<String>, MyError> {
+        let midi_out = MidiOutput::new("Push2_Soundboard-OUT")?;
+
+        Ok(midi_out
+            .ports()
+            .iter()
+            .filter_map(|port| midi_out.port_name(port).ok())
+            .collect())
     }
 
-    fn get_midi_in_port(midi_in: &MidiInput, port_name: &str) -> Result<MidiInputPort, MyError> {
-        // Get an input port (read from console if multiple are available)
+    fn get_midi_in_port(
+        midi_in: &MidiInput,
+        matcher: &DeviceMatcher,
+    ) -> Result<MidiInputPort, MyError> {
         let in_ports = midi_in.ports();
 
         let ip = in_ports
             .iter()
-            .find(|&x| midi_in.port_name(x).unwrap_or_default() == port_name.to_string())
-            .ok_or(MyError::NoMidiInFound)?;
-        Ok(ip.clone())
+            .find(|&x| matcher.matches(&midi_in.port_name(x).unwrap_or_default()));
+
+        match ip {
+            Some(port) => {
+                println!("Using midi input: {}", midi_in.port_name(port)?);
+                Ok(port.clone())
+            }
+            None => {
+                println!(
+                    "No midi input matches \"{}\", available are: {:?}",
+                    matcher.pattern(),
+                    MidiConnection::input_port_names()?
+                );
+                Err(MyError::NoMidiInFound)
+            }
+        }
     }
 
     fn get_midi_out_port(
         midi_out: &MidiOutput,
-        port_name: &str,
+        matcher: &DeviceMatcher,
     ) -> Result<MidiOutputPort, MyError> {
         let out_ports = midi_out.ports();
+
         let p = out_ports
             .iter()
-            .find(|&x| midi_out.port_name(x).unwrap_or_default() == port_name.to_string())
-            .ok_or(MyError::NoMidiOutFound)?;
-        Ok(p.clone())
+            .find(|&x| matcher.matches(&midi_out.port_name(x).unwrap_or_default()));
+
+        match p {
+            Some(port) => {
+                println!("Using midi output: {}", midi_out.port_name(port)?);
+                Ok(port.clone())
+            }
+            None => {
+                println!(
+                    "No midi output matches \"{}\", available are: {:?}",
+                    matcher.pattern(),
+                    MidiConnection::output_port_names()?
+                );
+                Err(MyError::NoMidiOutFound)
+            }
+        }
+    }
+}
+
+impl MidiBackend for MidiConnection {
+    fn send_to_device(&mut self, data: &[u8]) -> Result<(), MyError> {
+        self.out_conn.send(data)?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<MidiMessage> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// In-memory stand-in for the Push2.
+///
+/// Input is queued with `push_input`/`push_raw`, everything sent to the device is folded into the
+/// current light state of every address.
+#[derive(Default)]
+pub struct VirtualMidi {
+    inputs: VecDeque<MidiMessage>,
+    lights: LightState,
+}
+
+impl VirtualMidi {
+    pub fn new() -> VirtualMidi {
+        VirtualMidi::default()
+    }
+
+    pub fn push_input(&mut self, address: u8, value: i16) {
+        self.inputs.push_back(MidiMessage::Input(address, value));
+    }
+
+    /// Queues a raw MIDI message as if it came from the hardware.
+    pub fn push_raw(&mut self, message: &[u8]) {
+        if let Some(msg) = MidiMessage::parse(message) {
+            self.inputs.push_back(msg);
+        }
+    }
+
+    /// The last colour value sent to `address` with the given status byte (note on or control
+    /// change), 0 if it was never lit.
+    pub fn light(&self, status: u8, address: u8) -> u8 {
+        self.lights.get(&(status, address)).copied().unwrap_or(0)
+    }
+
+    pub fn lights(&self) -> &LightState {
+        &self.lights
+    }
+}
+
+impl MidiBackend for VirtualMidi {
+    fn send_to_device(&mut self, data: &[u8]) -> Result<(), MyError> {
+        apply_light_message(&mut self.lights, data);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<MidiMessage> {
+        self.inputs.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_encoder_is_relative() {
+        for address in [14, 15, 71, 72, 73, 74, 75, 76, 77, 78, 79] {
+            assert_eq!(
+                MidiMessage::parse(&[CONTROL_CHANGE, address, 1]),
+                Some(MidiMessage::Input(address, 1))
+            );
+            assert_eq!(
+                MidiMessage::parse(&[CONTROL_CHANGE, address, 127]),
+                Some(MidiMessage::Input(address, -1))
+            );
+            assert_eq!(
+                MidiMessage::parse(&[CONTROL_CHANGE, address, 120]),
+                Some(MidiMessage::Input(address, -8))
+            );
+        }
+    }
+
+    #[test]
+    fn buttons_keep_their_value() {
+        assert_eq!(
+            MidiMessage::parse(&[CONTROL_CHANGE, 85, 127]),
+            Some(MidiMessage::Input(85, 127))
+        );
+        assert_eq!(
+            MidiMessage::parse(&[NOTE_ON, 36, 127]),
+            Some(MidiMessage::Input(36, 127))
+        );
     }
 }
@@ -0,0 +1,400 @@
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use push2_display::Push2Display;
+
+use crate::{
+    discovery::{first_match, DeviceMatcher},
+    frame_buffer::FrameBuffer,
+    lock_or_return_err,
+    midi::{MidiBackend, MidiConnection, MidiMessage, SharedMidi},
+    MyError,
+};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The parts of a Push2 that `Push2Connection` opens, the hardware or a stand-in in the tests.
+pub trait Push2Device: Send + Sync {
+    fn open_midi(&self) -> Result<Box<dyn MidiBackend>, MyError>;
+
+    fn open_display(&self) -> Result<Box<dyn Push2Screen>, MyError>;
+
+    /// Whether the device can be opened again, asked from the search thread.
+    fn is_present(&self) -> bool;
+}
+
+/// The display of a Push2, flushing fails once the device is gone.
+pub trait Push2Screen {
+    fn draw(&mut self, frame: &FrameBuffer) -> Result<(), MyError>;
+
+    fn flush(&mut self) -> Result<(), MyError>;
+}
+
+impl Push2Screen for Push2Display {
+    fn draw(&mut self, frame: &FrameBuffer) -> Result<(), MyError> {
+        frame.draw_to(self)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), MyError> {
+        Push2Display::flush(self)?;
+        Ok(())
+    }
+}
+
+/// The Push2 on USB, its midi ports are found by name.
+struct UsbPush2 {
+    in_matcher: DeviceMatcher,
+    out_matcher: DeviceMatcher,
+}
+
+impl Push2Device for UsbPush2 {
+    fn open_midi(&self) -> Result<Box<dyn MidiBackend>, MyError> {
+        Ok(Box::new(MidiConnection::new(
+            &self.in_matcher,
+            &self.out_matcher,
+        )?))
+    }
+
+    fn open_display(&self) -> Result<Box<dyn Push2Screen>, MyError> {
+        Ok(Box::new(Push2Display::new()?))
+    }
+
+    fn is_present(&self) -> bool {
+        let in_found = MidiConnection::input_port_names()
+            .is_ok_and(|names| first_match(&self.in_matcher, &names).is_some());
+        let out_found = MidiConnection::output_port_names()
+            .is_ok_and(|names| first_match(&self.out_matcher, &names).is_some());
+
+        in_found && out_found
+    }
+}
+
+/// The midi side of the Push2, which survives the device going away.
+///
+/// While disconnected everything sent is dropped and nothing is received, so the device modes
+/// keep running and their light state is re-sent once the device is back.
+pub struct Push2Link {
+    connection: Option<Box<dyn MidiBackend>>,
+}
+
+impl Push2Link {
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+}
+
+impl MidiBackend for Push2Link {
+    fn send_to_device(&mut self, data: &[u8]) -> Result<(), MyError> {
+        if let Some(connection) = &mut self.connection
+            && let Err(err) = connection.send_to_device(data)
+        {
+            println!("Lost the Push2 midi connection: {}", err);
+            self.connection = None;
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<MidiMessage> {
+        self.connection.as_mut()?.receive()
+    }
+}
+
+/// Midi and display of the hardware Push2, reopened in the background when it gets unplugged.
+pub struct Push2Connection {
+    link: Arc<Mutex<Push2Link>>,
+    display: Option<Box<dyn Push2Screen>>,
+
+    device: Arc<dyn Push2Device>,
+    retry_interval: Duration,
+
+    device_found: Option<Receiver<()>>,
+}
+
+impl Push2Connection {
+    pub fn open(
+        in_matcher: DeviceMatcher,
+        out_matcher: DeviceMatcher,
+    ) -> Result<Push2Connection, MyError> {
+        let device = UsbPush2 {
+            in_matcher,
+            out_matcher,
+        };
+
+        Push2Connection::with_device(Arc::new(device), RETRY_INTERVAL)
+    }
+
+    /// Opens `device`, while it is gone it is looked for every `retry_interval`.
+    pub fn with_device(
+        device: Arc<dyn Push2Device>,
+        retry_interval: Duration,
+    ) -> Result<Push2Connection, MyError> {
+        let display = device.open_display()?;
+        let connection = device.open_midi()?;
+
+        Ok(Push2Connection {
+            link: Arc::new(Mutex::new(Push2Link {
+                connection: Some(connection),
+            })),
+            display: Some(display),
+            device,
+            retry_interval,
+            device_found: None,
+        })
+    }
+
+    pub fn midi(&self) -> SharedMidi {
+        self.link.clone()
+    }
+
+    /// Copies the frame to the display, a failing display counts as the device being gone.
+    pub fn draw(&mut self, frame: &FrameBuffer) -> Result<(), MyError> {
+        if let Some(display) = &mut self.display {
+            display.draw(frame)?;
+
+            if let Err(err) = display.flush() {
+                println!("Lost the Push2 display: {}", err);
+                self.display = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Watches the connection and reopens it once the device is back.
+    ///
+    /// Returns true when the device was reconnected, its lights need to be re-applied then.
+    pub fn update(&mut self) -> Result<bool, MyError> {
+        let link = &self.link;
+        let midi_connected = lock_or_return_err!(link).is_connected();
+
+        if midi_connected && self.display.is_some() {
+            return Ok(false);
+        }
+
+        let Some(device_found) = &self.device_found else {
+            // Just lost the device, drop what is left of it and start looking for it
+            println!("Push2 disconnected, waiting for it to come back.");
+            lock_or_return_err!(link).connection = None;
+            self.display = None;
+            self.device_found = Some(self.spawn_search());
+
+            return Ok(false);
+        };
+
+        match device_found.try_recv() {
+            Ok(()) => {}
+            Err(TryRecvError::Empty) => return Ok(false),
+            Err(TryRecvError::Disconnected) => {
+                self.device_found = Some(self.spawn_search());
+                return Ok(false);
+            }
+        }
+
+        match self.reopen() {
+            Ok(()) => {
+                println!("Push2 reconnected.");
+                self.device_found = None;
+                Ok(true)
+            }
+            Err(err) => {
+                // Probably still starting up, keep looking
+                println!("Could not reopen the Push2: {}", err);
+                self.device_found = Some(self.spawn_search());
+                Ok(false)
+            }
+        }
+    }
+
+    fn reopen(&mut self) -> Result<(), MyError> {
+        let display = self.device.open_display()?;
+        let connection = self.device.open_midi()?;
+
+        let link = &self.link;
+        lock_or_return_err!(link).connection = Some(connection);
+        self.display = Some(display);
+
+        Ok(())
+    }
+
+    /// Polls the device on a background thread and signals once it is present again.
+    fn spawn_search(&self) -> Receiver<()> {
+        let (sender, receiver) = channel();
+        let device = Arc::clone(&self.device);
+        let retry_interval = self.retry_interval;
+
+        thread::spawn(move || loop {
+            thread::sleep(retry_interval);
+
+            if device.is_present() {
+                // The main loop may have stopped caring, nothing to do then
+                let _ = sender.send(());
+                break;
+            }
+        });
+
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::atomic::{AtomicBool, Ordering},
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::{audio::output::OutputBackend, button_map::ButtonMap, sound_system::SoundSystem};
+
+    const PAD_0X0: u8 = 36;
+
+    /// A Push2 that can be unplugged, everything sent to its midi port is kept.
+    #[derive(Default)]
+    struct FakePush2 {
+        unplugged: Arc<AtomicBool>,
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    struct FakeMidi {
+        unplugged: Arc<AtomicBool>,
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    struct FakeScreen {
+        unplugged: Arc<AtomicBool>,
+    }
+
+    impl FakePush2 {
+        fn set_unplugged(&self, unplugged: bool) {
+            self.unplugged.store(unplugged, Ordering::SeqCst);
+        }
+
+        fn sent_to(&self, address: u8) -> bool {
+            self.sent
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|data| data.get(1) == Some(&address))
+        }
+    }
+
+    impl Push2Device for FakePush2 {
+        fn open_midi(&self) -> Result<Box<dyn MidiBackend>, MyError> {
+            if !self.is_present() {
+                return Err(MyError::NoMidiOutFound);
+            }
+
+            Ok(Box::new(FakeMidi {
+                unplugged: Arc::clone(&self.unplugged),
+                sent: Arc::clone(&self.sent),
+            }))
+        }
+
+        fn open_display(&self) -> Result<Box<dyn Push2Screen>, MyError> {
+            Ok(Box::new(FakeScreen {
+                unplugged: Arc::clone(&self.unplugged),
+            }))
+        }
+
+        fn is_present(&self) -> bool {
+            !self.unplugged.load(Ordering::SeqCst)
+        }
+    }
+
+    impl MidiBackend for FakeMidi {
+        fn send_to_device(&mut self, data: &[u8]) -> Result<(), MyError> {
+            if self.unplugged.load(Ordering::SeqCst) {
+                return Err(MyError::NoMidiOutFound);
+            }
+
+            self.sent.lock().unwrap().push(data.to_vec());
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Option<MidiMessage> {
+            None
+        }
+    }
+
+    impl Push2Screen for FakeScreen {
+        fn draw(&mut self, _frame: &FrameBuffer) -> Result<(), MyError> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), MyError> {
+            if self.unplugged.load(Ordering::SeqCst) {
+                return Err(MyError::NoMidiOutFound);
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_failed_send_drops_the_link() {
+        let device = FakePush2::default();
+        let mut link = Push2Link {
+            connection: Some(device.open_midi().unwrap()),
+        };
+
+        device.set_unplugged(true);
+        link.send_to_device(&[0b10010000, PAD_0X0, 5]).unwrap();
+        assert!(!link.is_connected());
+
+        // Nothing reaches the device after it came back, until it is reopened
+        device.set_unplugged(false);
+        link.send_to_device(&[0b10010000, PAD_0X0, 5]).unwrap();
+        assert!(!device.sent_to(PAD_0X0));
+    }
+
+    #[test]
+    fn the_lights_are_applied_again_after_a_reconnect() {
+        let device = Arc::new(FakePush2::default());
+        let mut push2 =
+            Push2Connection::with_device(device.clone(), Duration::from_millis(10)).unwrap();
+        let midiconn = push2.midi();
+
+        let sound_system = Arc::new(Mutex::new(
+            SoundSystem::new(&OutputBackend::Null(1.0)).unwrap(),
+        ));
+        let mut button_map = ButtonMap::new(
+            sound_system,
+            Path::new("config/buttonvalues.ron"),
+            Path::new("config/sound_config.ron"),
+            &midiconn,
+        )
+        .unwrap();
+        button_map.apply_button_lights(&midiconn).unwrap();
+        assert!(device.sent_to(PAD_0X0));
+
+        device.set_unplugged(true);
+        button_map.apply_button_lights(&midiconn).unwrap();
+        assert!(!push2.update().unwrap(), "reconnected while unplugged");
+
+        device.sent.lock().unwrap().clear();
+        device.set_unplugged(false);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut reconnected = false;
+
+        while !reconnected && Instant::now() < deadline {
+            reconnected = push2.update().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(reconnected, "the device was not found again");
+
+        // What the main loop does when `update` reports the device back
+        button_map.clear_button_lights(&midiconn).unwrap();
+        button_map.apply_button_lights(&midiconn).unwrap();
+        assert!(device.sent_to(PAD_0X0));
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    button_map::ButtonMap,
+    lock_or_return_err,
+    midi::{apply_light_message, LightState, MidiBackend, MidiMessage, SharedMidi, VirtualMidi},
+    sound_system::SoundSystem,
+    MyError,
+};
+
+const REPLAY_TICK: Duration = Duration::from_micros(1_000_000 / 60);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SessionEvent {
+    Input {
+        time_ms: u64,
+        address: u8,
+        value: i16,
+    },
+    Output {
+        time_ms: u64,
+        data: Vec<u8>,
+    },
+}
+
+/// Everything that went in and out of the Push2 during one run of the app.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Session {
+    events: Vec<SessionEvent>,
+}
+
+impl Session {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Session, MyError> {
+        let mut file = File::open(path)?;
+        let mut session_string = String::new();
+        file.read_to_string(&mut session_string)?;
+
+        Ok(ron::de::from_str(&session_string)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MyError> {
+        let session_string = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+
+        let mut file = File::create(path)?;
+        file.write_all(session_string.as_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn duration(&self) -> Duration {
+        let last_ms = match self.events.last() {
+            Some(SessionEvent::Input { time_ms, .. }) => *time_ms,
+            Some(SessionEvent::Output { time_ms, .. }) => *time_ms,
+            None => 0,
+        };
+
+        Duration::from_millis(last_ms)
+    }
+
+    /// The light state right before every input and once at the end of the session.
+    ///
+    /// Unlit addresses are left out, so clearing a light and never lighting it compare equal.
+    pub fn light_snapshots(&self) -> Vec<LightState> {
+        let mut lights = LightState::new();
+        let mut snapshots = vec![];
+
+        for event in &self.events {
+            match event {
+                SessionEvent::Input { .. } => snapshots.push(Session::lit_only(&lights)),
+                SessionEvent::Output { data, .. } => apply_light_message(&mut lights, data),
+            }
+        }
+
+        snapshots.push(Session::lit_only(&lights));
+        snapshots
+    }
+
+    fn lit_only(lights: &LightState) -> LightState {
+        let mut lit = lights.clone();
+        lit.retain(|_address, color| *color != 0);
+        lit
+    }
+}
+
+/// Wraps another backend and records every message going through it.
+///
+/// The clock only runs from `start_clock` on, everything before is recorded at 0 ms.
+pub struct RecordingMidi {
+    inner: SharedMidi,
+    session: Session,
+    start: Option<Instant>,
+    path: PathBuf,
+}
+
+impl RecordingMidi {
+    pub fn new<P: Into<PathBuf>>(inner: SharedMidi, path: P) -> RecordingMidi {
+        RecordingMidi {
+            inner,
+            session: Session::default(),
+            start: None,
+            path: path.into(),
+        }
+    }
+
+    /// Starts the clock of the recording, later calls keep it running.
+    ///
+    /// Replays start once the board loaded, so recordings should start there as well.
+    pub fn start_clock(&mut self) {
+        if self.start.is_none() {
+            self.start = Some(Instant::now());
+        }
+    }
+
+    pub fn save(&self) -> Result<(), MyError> {
+        self.session.save(&self.path)?;
+        println!("Saved session recording to {}", self.path.display());
+
+        Ok(())
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.start
+            .map_or(0, |start| start.elapsed().as_millis() as u64)
+    }
+}
+
+impl MidiBackend for RecordingMidi {
+    fn send_to_device(&mut self, data: &[u8]) -> Result<(), MyError> {
+        let time_ms = self.elapsed_ms();
+        self.session.events.push(SessionEvent::Output {
+            time_ms,
+            data: data.to_vec(),
+        });
+
+        let inner = &self.inner;
+        lock_or_return_err!(inner).send_to_device(data)
+    }
+
+    fn receive(&mut self) -> Option<MidiMessage> {
+        let msg = self.inner.try_lock().ok()?.receive();
+
+        if let Some(MidiMessage::Input(address, value)) = msg {
+            let time_ms = self.elapsed_ms();
+            self.session.events.push(SessionEvent::Input {
+                time_ms,
+                address,
+                value,
+            });
+        }
+
+        msg
+    }
+}
+
+/// Feeds the inputs of a recorded session into a fresh board in real time.
+///
+/// Returns the light state before every input and at the end, see `Session::light_snapshots`.
+pub fn replay(
+    session: &Session,
+    button_map: &mut ButtonMap,
+    virtual_midi: &Arc<Mutex<VirtualMidi>>,
+    midiconn: &SharedMidi,
+) -> Result<Vec<LightState>, MyError> {
+    let mut snapshots = vec![];
+    let start = Instant::now();
+
+    for event in &session.events {
+        if let SessionEvent::Input {
+            time_ms,
+            address,
+            value,
+        } = event
+        {
+            run_until(
+                start + Duration::from_millis(*time_ms),
+                button_map,
+                midiconn,
+            )?;
+
+            snapshots.push(Session::lit_only(
+                lock_or_return_err!(virtual_midi).lights(),
+            ));
+
+            button_map.activate_button(*address, *value, midiconn)?;
+        }
+    }
+
+    run_until(start + session.duration(), button_map, midiconn)?;
+    snapshots.push(Session::lit_only(
+        lock_or_return_err!(virtual_midi).lights(),
+    ));
+
+    Ok(snapshots)
+}
+
+/// Replays the session file against the board and checks the lights match the recording.
+pub fn replay_file(
+    path: &Path,
+    sound_system: Arc<Mutex<SoundSystem>>,
+    button_values_path: &Path,
+    board_path: &Path,
+) -> Result<(), MyError> {
+    let session = Session::load(path)?;
+
+    let virtual_midi = Arc::new(Mutex::new(VirtualMidi::new()));
+    let midiconn: SharedMidi = virtual_midi.clone();
+
+    let mut button_map = ButtonMap::new(sound_system, button_values_path, board_path, &midiconn)?;
+    button_map.clear_button_lights(&midiconn)?;
+    button_map.apply_button_lights(&midiconn)?;
+
+    // Sounds load in the background, the recording was made with a board ready to play
+    while button_map.is_loading() {
+        button_map.update(&midiconn)?;
+        thread::sleep(REPLAY_TICK);
+    }
+
+    let replayed = replay(&session, &mut button_map, &virtual_midi, &midiconn)?;
+    let recorded = session.light_snapshots();
+
+    match recorded
+        .iter()
+        .zip(replayed.iter())
+        .position(|(recorded, replayed)| recorded != replayed)
+    {
+        Some(index) => Err(MyError::ReplayMismatch(index)),
+        None => {
+            println!(
+                "Replayed {} inputs, light output matches the recording.",
+                recorded.len() - 1
+            );
+            Ok(())
+        }
+    }
+}
+
+fn run_until(
+    deadline: Instant,
+    button_map: &mut ButtonMap,
+    midiconn: &SharedMidi,
+) -> Result<(), MyError> {
+    while Instant::now() < deadline {
+        button_map.update(midiconn)?;
+        thread::sleep(REPLAY_TICK);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audio::output::OutputBackend, test_dir::TestDir};
+
+    const PAD_1X0: u8 = 37;
+
+    fn silent_sound_system() -> Arc<Mutex<SoundSystem>> {
+        Arc::new(Mutex::new(
+            SoundSystem::new(&OutputBackend::Null(1.0)).unwrap(),
+        ))
+    }
+
+    /// Records pressing the loop of the test board and letting it play for a moment, the way
+    /// the main loop drives the board.
+    fn record_session(path: &Path) {
+        let virtual_midi = Arc::new(Mutex::new(VirtualMidi::new()));
+        let recording = Arc::new(Mutex::new(RecordingMidi::new(virtual_midi.clone(), path)));
+        let midiconn: SharedMidi = recording.clone();
+
+        let mut button_map = ButtonMap::new(
+            silent_sound_system(),
+            Path::new("config/buttonvalues.ron"),
+            Path::new("config/sound_config.ron"),
+            &midiconn,
+        )
+        .unwrap();
+        button_map.clear_button_lights(&midiconn).unwrap();
+        button_map.apply_button_lights(&midiconn).unwrap();
+
+        while button_map.is_loading() {
+            button_map.update(&midiconn).unwrap();
+            thread::sleep(REPLAY_TICK);
+        }
+        recording.lock().unwrap().start_clock();
+
+        virtual_midi.lock().unwrap().push_input(PAD_1X0, 127);
+        let end = Instant::now() + Duration::from_millis(300);
+
+        while Instant::now() < end {
+            let msg = midiconn.lock().unwrap().receive();
+
+            if let Some(MidiMessage::Input(address, value)) = msg {
+                button_map
+                    .activate_button(address, value, &midiconn)
+                    .unwrap();
+            }
+
+            button_map.update(&midiconn).unwrap();
+            thread::sleep(REPLAY_TICK);
+        }
+
+        recording.lock().unwrap().save().unwrap();
+    }
+
+    fn replay_session(path: &Path) -> Result<(), MyError> {
+        replay_file(
+            path,
+            silent_sound_system(),
+            Path::new("config/buttonvalues.ron"),
+            Path::new("config/sound_config.ron"),
+        )
+    }
+
+    #[test]
+    fn a_recorded_session_replays_with_the_same_lights() {
+        let dir = TestDir::new("session_round_trip");
+        let path = dir.join("session.ron");
+        record_session(&path);
+
+        let session = Session::load(&path).unwrap();
+        let inputs = session
+            .events
+            .iter()
+            .filter(|event| matches!(event, SessionEvent::Input { .. }))
+            .count();
+        assert_eq!(inputs, 1);
+
+        replay_session(&path).unwrap();
+    }
+
+    #[test]
+    fn a_light_that_differs_from_the_recording_is_reported() {
+        let dir = TestDir::new("session_mismatch");
+        let path = dir.join("session.ron");
+        record_session(&path);
+
+        // Light a pad the board never lights at the very end
+        let mut session = Session::load(&path).unwrap();
+        session.events.push(SessionEvent::Output {
+            time_ms: session.duration().as_millis() as u64,
+            data: vec![0b10010000, 99, 5],
+        });
+        session.save(&path).unwrap();
+
+        assert!(matches!(
+            replay_session(&path),
+            Err(MyError::ReplayMismatch(1))
+        ));
+    }
+}
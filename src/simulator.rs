@@ -0,0 +1,356 @@
+use std::sync::{Arc, Mutex};
+
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+
+use crate::{
+    frame_buffer::{FrameBuffer, DISPLAY_HEIGHT, DISPLAY_WIDTH},
+    midi::{VirtualMidi, CONTROL_CHANGE, NOTE_ON},
+    MyError,
+};
+
+const PAD_SIZE: usize = 40;
+const PAD_GAP: usize = 4;
+const PAD_STRIDE: usize = PAD_SIZE + PAD_GAP;
+
+const GRID_X: usize = (DISPLAY_WIDTH as usize - 8 * PAD_STRIDE) / 2;
+const GRID_Y: usize = DISPLAY_HEIGHT as usize + 20;
+
+// The control buttons are stacked left of the pad grid
+const CONTROL_X: usize = GRID_X - 2 * PAD_STRIDE;
+
+const WINDOW_WIDTH: usize = DISPLAY_WIDTH as usize;
+const WINDOW_HEIGHT: usize = GRID_Y + 8 * PAD_STRIDE + 20;
+
+// Midi address of the bottom left pad (Pad0x0)
+const FIRST_PAD_ADDRESS: u8 = 36;
+
+// Control change buttons shown left of the grid, top to bottom
+const CONTROL_BUTTONS: [u8; 4] = [20, 21, 22, 29];
+
+const PAGE_LEFT_BUTTON: u8 = 62;
+const PAGE_RIGHT_BUTTON: u8 = 63;
+
+const MASTER_VOLUME_ENCODER: u8 = 78;
+const FIRST_DISPLAY_ENCODER: u8 = 71;
+const VOLUME_STEP: i16 = 5;
+
+const SNAPSHOT_PATH: &str = "snapshot.png";
+
+/// Desktop window standing in for the Push2.
+///
+/// Shows the rendered display and the pad lights of a `VirtualMidi` and feeds mouse and
+/// keyboard input back into it, so the regular `ButtonMap` pipeline handles it.
+pub struct Simulator {
+    window: Window,
+    buffer: Vec<u32>,
+    midi: Arc<Mutex<VirtualMidi>>,
+    mouse_was_down: bool,
+}
+
+impl Simulator {
+    pub fn new(midi: Arc<Mutex<VirtualMidi>>) -> Result<Simulator, MyError> {
+        let mut window = Window::new(
+            "Push2Soundboard Simulator",
+            WINDOW_WIDTH,
+            WINDOW_HEIGHT,
+            WindowOptions::default(),
+        )?;
+
+        // The game loop decides when to draw
+        window.set_target_fps(0);
+
+        println!("Simulator controls:");
+        println!("  Click pads and the buttons left of them");
+        println!("  Up/Down: master volume, Left/Right: first display encoder");
+        println!("  F1: sound mode, F2: spotify mode, F3: mixer, R: repress mode");
+        println!("  Page Up/Page Down: next/previous pad page");
+        println!("  S: save the display to {}", SNAPSHOT_PATH);
+
+        Ok(Simulator {
+            window,
+            buffer: vec![0; WINDOW_WIDTH * WINDOW_HEIGHT],
+            midi,
+            mouse_was_down: false,
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    /// Draws the current frame and lights, then queues any new input on the virtual device.
+    pub fn update(&mut self, frame: &FrameBuffer) -> Result<(), MyError> {
+        self.draw(frame)?;
+
+        self.window
+            .update_with_buffer(&self.buffer, WINDOW_WIDTH, WINDOW_HEIGHT)?;
+
+        self.handle_mouse()?;
+        self.handle_keys(frame)?;
+
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &FrameBuffer) -> Result<(), MyError> {
+        self.buffer.fill(0x202020);
+
+        // Display
+        for (index, rgb) in frame.to_rgb8().chunks(3).enumerate() {
+            let x = index % DISPLAY_WIDTH as usize;
+            let y = index / DISPLAY_WIDTH as usize;
+
+            self.buffer[y * WINDOW_WIDTH + x] =
+                ((rgb[0] as u32) << 16) | ((rgb[1] as u32) << 8) | rgb[2] as u32;
+        }
+
+        let midi = match self.midi.try_lock() {
+            Ok(value) => value,
+            Err(_) => return Err(MyError::MutexError("Could not lock virtual midi")),
+        };
+
+        // Pads
+        for row in 0..8 {
+            for column in 0..8 {
+                let address = FIRST_PAD_ADDRESS + (row * 8 + column) as u8;
+                let (x, y) = Simulator::pad_position(row, column);
+
+                Simulator::fill_rect(
+                    &mut self.buffer,
+                    x,
+                    y,
+                    PAD_SIZE,
+                    PAD_SIZE,
+                    Simulator::palette_color(midi.light(NOTE_ON, address)),
+                );
+            }
+        }
+
+        // Control buttons
+        for (index, address) in CONTROL_BUTTONS.iter().enumerate() {
+            Simulator::fill_rect(
+                &mut self.buffer,
+                CONTROL_X,
+                GRID_Y + index * PAD_STRIDE,
+                PAD_SIZE,
+                PAD_SIZE / 2,
+                Simulator::palette_color(midi.light(CONTROL_CHANGE, *address)),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn handle_mouse(&mut self) -> Result<(), MyError> {
+        let mouse_down = self.window.get_mouse_down(MouseButton::Left);
+        let clicked = mouse_down && !self.mouse_was_down;
+        self.mouse_was_down = mouse_down;
+
+        if !clicked {
+            return Ok(());
+        }
+
+        let Some((x, y)) = self.window.get_mouse_pos(MouseMode::Discard) else {
+            return Ok(());
+        };
+
+        let mut midi = match self.midi.try_lock() {
+            Ok(value) => value,
+            Err(_) => return Err(MyError::MutexError("Could not lock virtual midi")),
+        };
+
+        Simulator::click(&mut midi, x as usize, y as usize);
+
+        Ok(())
+    }
+
+    /// Queues the press of the pad or control button at a window position, if there is one.
+    fn click(midi: &mut VirtualMidi, x: usize, y: usize) {
+        for row in 0..8 {
+            for column in 0..8 {
+                let (pad_x, pad_y) = Simulator::pad_position(row, column);
+
+                if (pad_x..pad_x + PAD_SIZE).contains(&x) && (pad_y..pad_y + PAD_SIZE).contains(&y)
+                {
+                    midi.push_raw(&[NOTE_ON, FIRST_PAD_ADDRESS + (row * 8 + column) as u8, 127]);
+                }
+            }
+        }
+
+        for (index, address) in CONTROL_BUTTONS.iter().enumerate() {
+            let button_y = GRID_Y + index * PAD_STRIDE;
+
+            if (CONTROL_X..CONTROL_X + PAD_SIZE).contains(&x)
+                && (button_y..button_y + PAD_SIZE / 2).contains(&y)
+            {
+                midi.push_raw(&[CONTROL_CHANGE, *address, 127]);
+            }
+        }
+    }
+
+    fn handle_keys(&mut self, frame: &FrameBuffer) -> Result<(), MyError> {
+        let keys = self.window.get_keys_pressed(KeyRepeat::Yes);
+
+        let mut midi = match self.midi.try_lock() {
+            Ok(value) => value,
+            Err(_) => return Err(MyError::MutexError("Could not lock virtual midi")),
+        };
+
+        for key in keys {
+            if key == Key::S {
+                frame.save_png(SNAPSHOT_PATH)?;
+                println!("Saved display snapshot to {}", SNAPSHOT_PATH);
+            } else {
+                Simulator::press_key(&mut midi, key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queues the device input a key stands for, if any.
+    fn press_key(midi: &mut VirtualMidi, key: Key) {
+        match key {
+            Key::Up => midi.push_input(MASTER_VOLUME_ENCODER, VOLUME_STEP),
+            Key::Down => midi.push_input(MASTER_VOLUME_ENCODER, -VOLUME_STEP),
+            Key::Right => midi.push_input(FIRST_DISPLAY_ENCODER, 1),
+            Key::Left => midi.push_input(FIRST_DISPLAY_ENCODER, -1),
+            Key::F1 => midi.push_raw(&[CONTROL_CHANGE, CONTROL_BUTTONS[0], 127]),
+            Key::F2 => midi.push_raw(&[CONTROL_CHANGE, CONTROL_BUTTONS[1], 127]),
+            Key::F3 => midi.push_raw(&[CONTROL_CHANGE, CONTROL_BUTTONS[2], 127]),
+            Key::R => midi.push_raw(&[CONTROL_CHANGE, CONTROL_BUTTONS[3], 127]),
+            Key::PageUp => midi.push_raw(&[CONTROL_CHANGE, PAGE_RIGHT_BUTTON, 127]),
+            Key::PageDown => midi.push_raw(&[CONTROL_CHANGE, PAGE_LEFT_BUTTON, 127]),
+            _ => {}
+        }
+    }
+
+    /// Top left corner of a pad, row 0 is the bottom row like on the device.
+    fn pad_position(row: usize, column: usize) -> (usize, usize) {
+        (
+            GRID_X + column * PAD_STRIDE,
+            GRID_Y + (7 - row) * PAD_STRIDE,
+        )
+    }
+
+    fn fill_rect(buffer: &mut [u32], x: usize, y: usize, width: usize, height: usize, color: u32) {
+        for row in y..y + height {
+            buffer[row * WINDOW_WIDTH + x..row * WINDOW_WIDTH + x + width].fill(color);
+        }
+    }
+
+    /// Approximates the Push2 default colour palette.
+    ///
+    /// The indices used by the soundboard are exact, everything else is spread over the hue
+    /// circle like on the device.
+    fn palette_color(index: u8) -> u32 {
+        match index {
+            0 => 0x404040,
+            122 => 0xFFFFFF,
+            123 => 0xC0C0C0,
+            124 => 0x606060,
+            125 => 0x0050FF,
+            126 => 0x00FF00,
+            127 => 0xFF0000,
+            _ => {
+                let hue = (index as f32 - 1.0) / 121.0 * 6.0;
+                let fraction = hue.fract();
+
+                let (r, g, b) = match hue as u32 {
+                    0 => (1.0, fraction, 0.0),
+                    1 => (1.0 - fraction, 1.0, 0.0),
+                    2 => (0.0, 1.0, fraction),
+                    3 => (0.0, 1.0 - fraction, 1.0),
+                    4 => (fraction, 0.0, 1.0),
+                    _ => (1.0, 0.0, 1.0 - fraction),
+                };
+
+                ((r * 255.0) as u32) << 16 | ((g * 255.0) as u32) << 8 | (b * 255.0) as u32
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        button_map::{tests::test_board, ButtonMap},
+        midi::{MidiBackend, MidiMessage, SharedMidi},
+    };
+
+    /// Hands the queued input to the button map, like the main loop does.
+    fn deliver(button_map: &mut ButtonMap, midiconn: &SharedMidi) {
+        loop {
+            let msg = midiconn.lock().unwrap().receive();
+
+            match msg {
+                Some(MidiMessage::Input(address, value)) => button_map
+                    .activate_button(address, value, midiconn)
+                    .unwrap(),
+                None => break,
+            }
+        }
+    }
+
+    #[test]
+    fn clicking_a_pad_presses_it() {
+        let (mut button_map, virtual_midi, midiconn) = test_board();
+
+        // Pad1x0, the looping sound of the test board
+        let (x, y) = Simulator::pad_position(0, 1);
+        Simulator::click(&mut virtual_midi.lock().unwrap(), x + 1, y + 1);
+        deliver(&mut button_map, &midiconn);
+
+        assert_eq!(
+            virtual_midi
+                .lock()
+                .unwrap()
+                .light(NOTE_ON, FIRST_PAD_ADDRESS + 1),
+            127
+        );
+
+        // Between the pads
+        Simulator::click(&mut virtual_midi.lock().unwrap(), x - 1, y);
+        assert!(virtual_midi.lock().unwrap().receive().is_none());
+    }
+
+    #[test]
+    fn function_keys_switch_modes() {
+        let (mut button_map, virtual_midi, midiconn) = test_board();
+
+        Simulator::press_key(&mut virtual_midi.lock().unwrap(), Key::F3);
+        deliver(&mut button_map, &midiconn);
+        {
+            let midi = virtual_midi.lock().unwrap();
+            assert_eq!(midi.light(CONTROL_CHANGE, CONTROL_BUTTONS[2]), 122);
+            assert_eq!(midi.light(NOTE_ON, FIRST_PAD_ADDRESS + 1), 0);
+        }
+
+        Simulator::press_key(&mut virtual_midi.lock().unwrap(), Key::F1);
+        deliver(&mut button_map, &midiconn);
+        {
+            let midi = virtual_midi.lock().unwrap();
+            assert_eq!(midi.light(CONTROL_CHANGE, CONTROL_BUTTONS[2]), 123);
+            assert_eq!(midi.light(NOTE_ON, FIRST_PAD_ADDRESS + 1), 125);
+        }
+    }
+
+    #[test]
+    fn arrow_keys_turn_the_encoders() {
+        let mut midi = VirtualMidi::new();
+
+        Simulator::press_key(&mut midi, Key::Up);
+        Simulator::press_key(&mut midi, Key::Left);
+        Simulator::press_key(&mut midi, Key::A);
+
+        assert_eq!(
+            midi.receive(),
+            Some(MidiMessage::Input(MASTER_VOLUME_ENCODER, VOLUME_STEP))
+        );
+        assert_eq!(
+            midi.receive(),
+            Some(MidiMessage::Input(FIRST_DISPLAY_ENCODER, -1))
+        );
+        assert_eq!(midi.receive(), None);
+    }
+}
@@ -1,9 +1,33 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc,
+    },
+    thread,
+};
+
 use rodio::{
-    cpal::{traits::HostTrait, Host},
-    Device, DeviceTrait, OutputStream, OutputStreamHandle, Sink,
+    cpal::traits::HostTrait,
+    dynamic_mixer::{self, DynamicMixerController},
+    DeviceTrait, OutputStream, Sink, Source,
 };
 
-use crate::MyError;
+use crate::{
+    audio::{
+        bus::{Bus, BusConfig, BusInput},
+        channel::Channel,
+        ducking::{DuckingConfig, DuckingRule},
+        envelope::{EnvelopeControl, GainEnvelope, VOLUME_RAMP},
+        fade::FadeCurve,
+        latency::LatencyStats,
+        limiter::{GainReduction, Limiter},
+        meter::{LevelMeter, MeterTap},
+        output::{MasterMix, OfflineOutput, OutputBackend, DEFAULT_CHANNELS, DEFAULT_SAMPLE_RATE},
+    },
+    discovery::DeviceMatcher,
+    MyError,
+};
 
 #[derive(Clone, Copy)]
 pub enum RepressMode {
@@ -11,13 +35,47 @@ pub enum RepressMode {
     Interrupt,
 }
 
-#[allow(dead_code)]
-pub struct SoundSystem {
-    host: Host,
-    device: Device,
+/// What the master mix runs through on its way out, after all sounds are mixed.
+struct MasterChain {
+    volume: EnvelopeControl,
+    reduction: Arc<GainReduction>,
+    meter: Arc<LevelMeter>,
+}
+
+impl MasterChain {
+    fn new() -> MasterChain {
+        MasterChain {
+            volume: EnvelopeControl::new(1.0),
+            reduction: Arc::new(GainReduction::default()),
+            meter: Arc::new(LevelMeter::default()),
+        }
+    }
+
+    /// The volume comes first, so the limiter catches what turning it up pushes above full scale.
+    fn wrap(&self, mix: MasterMix) -> impl Source<Item = f32> + Send + 'static {
+        let master = GainEnvelope::new(mix, self.volume.clone());
+        let master = Limiter::new(master, self.reduction.clone());
+
+        MeterTap::new(master, self.meter.clone())
+    }
+}
+
+enum AudioOutput {
+    // The output stream can't leave the thread it was opened on, it plays on its own thread until
+    // this sender is dropped
+    Device { _close: Sender<()> },
+    Offline(OfflineOutput),
+}
 
-    stream: OutputStream,
-    stream_handle: OutputStreamHandle,
+pub struct SoundSystem {
+    mixer: Arc<DynamicMixerController<f32>>,
+    output: AudioOutput,
+    master: MasterChain,
+    latency: Arc<LatencyStats>,
+    buses: Vec<Bus>,
+    ducking: Vec<DuckingRule>,
+    // Sounds that played since they were last seen idle, in the order they started
+    channels: Vec<Channel>,
 
     pub repress_mode: RepressMode,
 
@@ -25,7 +83,57 @@ pub struct SoundSystem {
 }
 
 impl SoundSystem {
-    pub fn new<S: AsRef<str>>(device: S) -> Result<SoundSystem, MyError> {
+    pub fn new(backend: &OutputBackend) -> Result<SoundSystem, MyError> {
+        let master = MasterChain::new();
+
+        let (mixer, output) = match backend {
+            OutputBackend::Device(matcher) => match SoundSystem::open_device(matcher, &master) {
+                Ok(value) => value,
+                Err(err) => {
+                    println!("{}, continuing without audio output.", err);
+                    SoundSystem::open_offline(None, 1.0, &master)?
+                }
+            },
+            OutputBackend::Null(speed) => SoundSystem::open_offline(None, *speed, &master)?,
+            OutputBackend::WavFile(path, speed) => {
+                SoundSystem::open_offline(Some(path.clone()), *speed, &master)?
+            }
+        };
+
+        Ok(SoundSystem {
+            mixer,
+            output,
+            master,
+            latency: Arc::new(LatencyStats::default()),
+            buses: vec![],
+            ducking: vec![],
+            channels: vec![],
+            repress_mode: RepressMode::End,
+            volume: crate::DEFAULT_VOLUME,
+        })
+    }
+
+    pub fn output_device_names() -> Result<Vec<String>, MyError> {
+        let default_host = rodio::cpal::default_host();
+
+        match default_host.output_devices() {
+            Ok(devices) => Ok(devices.filter_map(|device| device.name().ok()).collect()),
+            Err(_) => Err(MyError::SoundSystemError("Device List not found.")),
+        }
+    }
+
+    /// The output opened when no device matches.
+    pub fn default_output_device_name() -> Option<String> {
+        rodio::cpal::default_host()
+            .default_output_device()?
+            .name()
+            .ok()
+    }
+
+    fn open_device(
+        matcher: &DeviceMatcher,
+        master_chain: &MasterChain,
+    ) -> Result<(Arc<DynamicMixerController<f32>>, AudioOutput), MyError> {
         let default_host = rodio::cpal::default_host();
         let device_list = default_host.output_devices();
 
@@ -34,15 +142,24 @@ impl SoundSystem {
             Err(_) => return Err(MyError::SoundSystemError("Device List not found.")),
         };
 
-        let opt_device = device_list
-            .find(|x| x.name().expect("Device name could not be parsed.") == device.as_ref());
+        let opt_device = device_list.find(|x| matcher.matches(&x.name().unwrap_or_default()));
 
         let device = match opt_device {
-            Some(value) => value,
+            Some(value) => {
+                println!("Using audio output: {}", value.name().unwrap_or_default());
+                value
+            }
             None => {
                 let default_device = default_host.default_output_device();
                 match default_device {
-                    Some(value) => value,
+                    Some(value) => {
+                        println!(
+                            "No audio output matches \"{}\", using the default output: {}",
+                            matcher.pattern(),
+                            value.name().unwrap_or_default()
+                        );
+                        value
+                    }
                     None => {
                         return Err(MyError::SoundSystemError("Could not create output device."))
                     }
@@ -50,39 +167,254 @@ impl SoundSystem {
             }
         };
 
-        let stream_res = OutputStream::try_from_device(&device);
-
-        let (stream, stream_handle) = match stream_res {
-            Ok(value) => value,
-            Err(_) => return Err(MyError::SoundSystemError("Could not create output stream.")),
+        let (channels, sample_rate) = match device.default_output_config() {
+            Ok(config) => (config.channels(), config.sample_rate().0),
+            Err(_) => (DEFAULT_CHANNELS, DEFAULT_SAMPLE_RATE),
         };
 
-        Ok(SoundSystem {
-            host: default_host,
-            device,
-            stream,
-            stream_handle,
-            repress_mode: RepressMode::End,
-            volume: crate::DEFAULT_VOLUME,
-        })
+        let (mixer, master) = dynamic_mixer::mixer(channels, sample_rate);
+
+        let master = master_chain.wrap(MasterMix::new(master));
+
+        let (opened_sender, opened) = channel();
+        let (close, closed) = channel::<()>();
+
+        thread::spawn(move || {
+            let (_stream, stream_handle) = match OutputStream::try_from_device(&device) {
+                Ok(value) => value,
+                Err(_) => {
+                    let _ = opened_sender.send(Err("Could not create output stream."));
+                    return;
+                }
+            };
+
+            if stream_handle.play_raw(master).is_err() {
+                let _ = opened_sender.send(Err("Could not play the master mix."));
+                return;
+            }
+
+            let _ = opened_sender.send(Ok(()));
+
+            // Keeps the stream open until the sound system is dropped
+            let _ = closed.recv();
+        });
+
+        match opened.recv() {
+            Ok(Ok(())) => Ok((mixer, AudioOutput::Device { _close: close })),
+            Ok(Err(err)) => Err(MyError::SoundSystemError(err)),
+            Err(_) => Err(MyError::SoundSystemError("Could not create output stream.")),
+        }
     }
 
-    pub fn get_sink(&mut self) -> Result<Sink, MyError> {
-        match Sink::try_new(&self.stream_handle) {
-            Ok(value) => Ok(value),
-            Err(_) => Err(MyError::SoundSystemError("Could not create a sink.")),
+    fn open_offline(
+        wav_path: Option<PathBuf>,
+        speed: f32,
+        master_chain: &MasterChain,
+    ) -> Result<(Arc<DynamicMixerController<f32>>, AudioOutput), MyError> {
+        let (mixer, master) = dynamic_mixer::mixer(DEFAULT_CHANNELS, DEFAULT_SAMPLE_RATE);
+
+        let master = master_chain.wrap(MasterMix::new(master));
+        let output = OfflineOutput::start(master, wav_path, speed)?;
+
+        Ok((mixer, AudioOutput::Offline(output)))
+    }
+
+    /// Stops an offline output so a WAV file being rendered is complete.
+    pub fn shutdown(&mut self) -> Result<(), MyError> {
+        if let AudioOutput::Offline(output) = &mut self.output {
+            output.stop()?;
         }
+
+        if let Some(report) = self.latency.report() {
+            println!("{}", report);
+        }
+
+        Ok(())
+    }
+
+    /// Shared with the playing sounds, which record their trigger latency into it.
+    pub fn latency_stats(&self) -> Arc<LatencyStats> {
+        self.latency.clone()
+    }
+
+    pub fn get_sink(&mut self) -> Result<Sink, MyError> {
+        let (sink, queue) = Sink::new_idle();
+        self.mixer.add(queue);
+
+        Ok(sink)
     }
 
     pub fn change_volume(&mut self, change: i16) {
         if change.is_negative() {
-            self.volume = self.volume.saturating_sub(change.abs() as u32);
+            self.volume = self.volume.saturating_sub(change.unsigned_abs() as u32);
         } else {
             self.volume = u32::min(crate::MAX_VOLUME, self.volume.saturating_add(change as u32));
         }
+
+        self.master
+            .volume
+            .ramp_to(self.get_volume_factor(), VOLUME_RAMP, FadeCurve::Linear);
+    }
+
+    /// Level of everything that goes out, after the master volume.
+    pub fn master_meter(&self) -> Arc<LevelMeter> {
+        self.master.meter.clone()
+    }
+
+    /// How much the master limiter turns the output down to keep it from clipping.
+    pub fn limiter_reduction(&self) -> Arc<GainReduction> {
+        self.master.reduction.clone()
     }
 
     pub fn get_volume_factor(&self) -> f32 {
         self.volume as f32 / (crate::DEFAULT_VOLUME as f32)
     }
+
+    /// Takes over the buses of a newly loaded board.
+    ///
+    /// Buses that were there before keep their volume and control, so the sounds playing on
+    /// them stay under it.
+    pub fn set_buses(&mut self, configs: &[BusConfig]) {
+        let mut old_buses = std::mem::take(&mut self.buses);
+
+        for config in configs {
+            let bus = match old_buses.iter().position(|bus| bus.name() == config.name) {
+                Some(index) => old_buses.swap_remove(index),
+                None => Bus::new(config),
+            };
+
+            self.buses.push(bus);
+        }
+    }
+
+    pub fn buses(&self) -> &[Bus] {
+        &self.buses
+    }
+
+    /// What a playback on the bus called `name` runs through, sounds without a bus get a
+    /// unity one.
+    pub fn bus_input(&self, name: Option<&str>) -> BusInput {
+        name.and_then(|name| self.buses.iter().find(|bus| bus.name() == name))
+            .map(|bus| bus.input())
+            .unwrap_or_else(BusInput::unity)
+    }
+
+    /// Adds the channel of a sound that starts playing, if it isn't known already.
+    pub fn register_channel(&mut self, channel: &Channel) {
+        if !self.channels.iter().any(|known| known.is_same(channel)) {
+            self.channels.push(channel.clone());
+        }
+    }
+
+    /// The channels of the sounds playing right now, in the order they started.
+    pub fn playing_channels(&mut self) -> Vec<Channel> {
+        self.channels.retain(|channel| channel.is_playing());
+        self.channels.clone()
+    }
+
+    pub fn set_ducking(&mut self, configs: &[DuckingConfig]) {
+        self.ducking = configs.iter().map(DuckingRule::new).collect();
+    }
+
+    /// Ducks every bus targeted by a rule whose trigger bus is playing, and brings back the
+    /// others. The deepest rule wins if several duck the same bus.
+    pub fn update_ducking(&mut self) {
+        for index in 0..self.buses.len() {
+            let rule = self
+                .ducking
+                .iter()
+                .filter(|rule| rule.target_bus == self.buses[index].name())
+                .filter(|rule| {
+                    self.buses
+                        .iter()
+                        .any(|bus| bus.name() == rule.trigger_bus && bus.is_playing())
+                })
+                .min_by(|a, b| a.gain.total_cmp(&b.gain));
+
+            self.buses[index].duck(rule);
+        }
+    }
+
+    pub fn change_bus_volume(&mut self, index: usize, change: i16) {
+        if let Some(bus) = self.buses.get_mut(index) {
+            bus.change_volume(change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+    use crate::audio::bus::BusSource;
+
+    /// A board with music ducked by voice and effects, the ramps take one sample.
+    fn ducking_system(rules: &[(&str, f32)]) -> SoundSystem {
+        let mut sound_system = SoundSystem::new(&OutputBackend::Null(1.0)).unwrap();
+        let buses: Vec<BusConfig> = ["music", "voice", "effects"]
+            .iter()
+            .map(|name| BusConfig {
+                name: name.to_string(),
+                volume: 1.0,
+            })
+            .collect();
+        let rules: Vec<DuckingConfig> = rules
+            .iter()
+            .map(|(trigger_bus, amount)| DuckingConfig {
+                trigger_bus: trigger_bus.to_string(),
+                target_bus: "music".to_string(),
+                amount: *amount,
+                attack_ms: 1,
+                release_ms: 1,
+            })
+            .collect();
+
+        sound_system.set_buses(&buses);
+        sound_system.set_ducking(&rules);
+        sound_system
+    }
+
+    /// A playback of full scale samples on `bus`, it plays until dropped.
+    fn play_on(sound_system: &SoundSystem, bus: &str) -> BusSource<SamplesBuffer<f32>> {
+        sound_system
+            .bus_input(Some(bus))
+            .attach(SamplesBuffer::new(1, 1000, vec![1.0f32; 1000]))
+    }
+
+    /// The gain of the music bus once its ramp is done.
+    fn music_gain(sound_system: &SoundSystem) -> f32 {
+        play_on(sound_system, "music").nth(10).unwrap()
+    }
+
+    #[test]
+    fn the_deepest_ducking_rule_wins() {
+        let mut sound_system = ducking_system(&[("voice", 0.5), ("effects", 0.75)]);
+
+        let _voice = play_on(&sound_system, "voice");
+        sound_system.update_ducking();
+        assert_eq!(music_gain(&sound_system), 0.5);
+
+        let _effects = play_on(&sound_system, "effects");
+        sound_system.update_ducking();
+        assert_eq!(music_gain(&sound_system), 0.25);
+    }
+
+    #[test]
+    fn ducking_ends_with_the_last_trigger_sound() {
+        let mut sound_system = ducking_system(&[("voice", 0.5)]);
+
+        let first = play_on(&sound_system, "voice");
+        let second = play_on(&sound_system, "voice");
+        sound_system.update_ducking();
+        assert_eq!(music_gain(&sound_system), 0.5);
+
+        drop(first);
+        sound_system.update_ducking();
+        assert_eq!(music_gain(&sound_system), 0.5);
+
+        drop(second);
+        sound_system.update_ducking();
+        assert_eq!(music_gain(&sound_system), 1.0);
+    }
 }
@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+
+/// A directory of its own for the files of one test, removed again when the test ends.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> TestDir {
+        let dir =
+            std::env::temp_dir().join(format!("push2_soundboard_{}_{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        TestDir(dir)
+    }
+
+    pub fn join(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+
+    /// Copies the test sound to `file`, which may be in a subdirectory.
+    pub fn add_sound(&self, file: &str) -> PathBuf {
+        let path = self.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::copy("assets/testsound.wav", &path).unwrap();
+
+        path
+    }
+
+    /// Writes `seconds` of silence to `file`, a small mono file however long it is.
+    pub fn add_silence(&self, file: &str, seconds: u32) -> PathBuf {
+        let path = self.join(file);
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..seconds * spec.sample_rate {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        path
+    }
+
+    /// Writes a board with `actions`, over the one written before for a reload.
+    pub fn write_board(&self, actions: &[String]) -> PathBuf {
+        let path = self.join("board.ron");
+        std::fs::write(
+            &path,
+            format!("ActionConfigs(actions: [{}])", actions.join(", ")),
+        )
+        .unwrap();
+
+        path
+    }
+
+    /// Writes a board with named `pages` of actions and no actions outside of them.
+    pub fn write_pages(&self, pages: &[(&str, &[String])]) -> PathBuf {
+        let pages: Vec<String> = pages
+            .iter()
+            .map(|(name, actions)| format!("(name: {:?}, actions: [{}])", name, actions.join(", ")))
+            .collect();
+
+        let path = self.join("board.ron");
+        std::fs::write(
+            &path,
+            format!("ActionConfigs(pages: [{}])", pages.join(", ")),
+        )
+        .unwrap();
+
+        path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A sound on `pad` playing `path` at full gain, `options` are more fields of its config.
+pub fn sound_config(pad: &str, path: &Path, looping: bool, options: &str) -> String {
+    format!(
+        "SoundConfig(button: Note({}), path: {:?}, looping: {}, fade_in: false, \
+         fade_out: false, gain: 1.0{})",
+        pad,
+        path.to_str().unwrap(),
+        looping,
+        options
+    )
+}
+
+/// A scene on `pad` fading to `sounds`, given by name and gain.
+pub fn scene_config(pad: &str, sounds: &[(&str, f32)]) -> String {
+    let sounds: Vec<String> = sounds
+        .iter()
+        .map(|(name, gain)| format!("(name: {:?}, gain: {:?})", name, gain))
+        .collect();
+
+    format!(
+        "SceneConfig(button: Note({}), sounds: [{}])",
+        pad,
+        sounds.join(", ")
+    )
+}